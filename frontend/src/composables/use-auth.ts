@@ -26,20 +26,21 @@ const useAuth = defineStore(
   "librarian.auth",
   () => {
     const token = ref<string>();
+    // authenticated by the reverse proxy (forward-auth), no token needed
+    const proxied = ref(false);
 
-    const isLoggedIn = computed(() => !!token.value);
+    const isLoggedIn = computed(() => !!token.value || proxied.value);
 
     async function test() {
       try {
         const resp = await fetch(makeUrl("/api/auth/test"), {
-          headers: {
-            Authorization: `Bearer ${token.value}`,
-          },
+          headers: token.value ? { Authorization: `Bearer ${token.value}` } : {},
         });
         const data = await resp.json();
 
         if (!data.ok) {
           token.value = undefined;
+          proxied.value = false;
 
           throw new Error(data.error);
         }
@@ -47,12 +48,24 @@ const useAuth = defineStore(
         console.error(error);
 
         token.value = undefined;
+        proxied.value = false;
 
         throw error;
       }
     }
 
-    async function login(loginToken: string) {
+    async function detectProxyAuth() {
+      try {
+        const resp = await fetch(makeUrl("/api/auth/test"));
+        const data = await resp.json();
+
+        proxied.value = !!data.ok && data.data?.method === "forwardAuth";
+      } catch {
+        proxied.value = false;
+      }
+    }
+
+    async function login(loginToken: string): Promise<string | undefined> {
       // test with api
       try {
         const resp = await fetch(makeUrl("/api/auth/login"), {
@@ -64,8 +77,36 @@ const useAuth = defineStore(
         });
         const data = await resp.json();
 
+        if (!data.ok) {
+          throw new Error(data.error);
+        }
+
+        if (data.data.mfaRequired) {
+          // return the challenge, the caller need to ask for the TOTP code
+          return data.data.challenge as string;
+        }
+
+        token.value = data.data.token;
+      } catch (error) {
+        console.error(error);
+
+        throw error;
+      }
+    }
+
+    async function loginTotp(challenge: string, code: string) {
+      try {
+        const resp = await fetch(makeUrl("/api/auth/login/totp"), {
+          method: "POST",
+          body: JSON.stringify({ challenge, code }),
+          headers: {
+            "Content-Type": "application/json",
+          },
+        });
+        const data = await resp.json();
+
         if (data.ok) {
-          token.value = loginToken;
+          token.value = data.data.token;
         } else {
           throw new Error(data.error);
         }
@@ -76,16 +117,31 @@ const useAuth = defineStore(
       }
     }
 
-    function logout() {
+    async function logout() {
+      try {
+        await fetch(makeUrl("/api/auth/logout"), {
+          method: "POST",
+          headers: {
+            Authorization: `Bearer ${token.value}`,
+          },
+        });
+      } catch (error) {
+        console.error(error);
+      }
+
       token.value = undefined;
+      proxied.value = false;
     }
 
     return {
       token,
+      proxied,
       isLoggedIn,
       login,
+      loginTotp,
       logout,
       test,
+      detectProxyAuth,
     };
   },
   {
@@ -22,6 +22,17 @@ export function makeUrl(url: string): string {
   return `/${url}`;
 }
 
+// Thrown for error responses, with the field-level errors if the backend gave any
+export class BackendError extends Error {
+  fields?: Record<string, string[]>;
+
+  constructor(message: string, fields?: Record<string, string[]>) {
+    super(message);
+    this.name = "BackendError";
+    this.fields = fields;
+  }
+}
+
 export default function useBackendFetch<T>(url: string, fetchOptions?: RequestInit): Promise<T> {
   const auth = useAuth();
 
@@ -43,7 +54,12 @@ export default function useBackendFetch<T>(url: string, fetchOptions?: RequestIn
           return resp.json();
         }
 
-        throw new Error(resp.statusText);
+        return resp
+          .json()
+          .catch(() => ({}))
+          .then((json) => {
+            throw new BackendError(resp.statusText, json.fields);
+          });
       })
       .then((json) => {
         if (json.ok) {
@@ -0,0 +1,121 @@
+import useBackendFetch, { makeUrl } from "./use-backend-fetch";
+import useAuth from "./use-auth";
+
+function fromBase64Url(data: string): ArrayBuffer {
+  const base64 = data.replace(/-/g, "+").replace(/_/g, "/");
+  const padded = base64.padEnd(base64.length + ((4 - (base64.length % 4)) % 4), "=");
+  const binary = atob(padded);
+
+  return Uint8Array.from(binary, (c) => c.charCodeAt(0)).buffer;
+}
+
+function toBase64Url(data: ArrayBuffer): string {
+  const binary = String.fromCharCode(...new Uint8Array(data));
+
+  return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+interface CredentialDescriptor {
+  type: "public-key";
+  id: string;
+}
+
+export async function registerPasskey(name: string) {
+  // eslint-disable-next-line @typescript-eslint/no-explicit-any
+  const options = await useBackendFetch<any>("/api/auth/webauthn/register/start", { method: "POST" });
+
+  const credential = (await navigator.credentials.create({
+    publicKey: {
+      ...options,
+      challenge: fromBase64Url(options.challenge),
+      user: {
+        ...options.user,
+        id: fromBase64Url(options.user.id),
+      },
+      excludeCredentials: options.excludeCredentials.map((cred: CredentialDescriptor) => ({
+        ...cred,
+        id: fromBase64Url(cred.id),
+      })),
+    },
+  })) as PublicKeyCredential | null;
+
+  if (!credential) {
+    throw new Error("Passkey registration cancelled");
+  }
+
+  const response = credential.response as AuthenticatorAttestationResponse;
+
+  return await useBackendFetch("/api/auth/webauthn/register/finish", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      name,
+      credential: {
+        id: credential.id,
+        rawId: toBase64Url(credential.rawId),
+        type: credential.type,
+        response: {
+          clientDataJSON: toBase64Url(response.clientDataJSON),
+          attestationObject: toBase64Url(response.attestationObject),
+        },
+      },
+    }),
+  });
+}
+
+export async function loginWithPasskey() {
+  const auth = useAuth();
+
+  const startResp = await fetch(makeUrl("/api/auth/webauthn/login/start"), { method: "POST" });
+  const startData = await startResp.json();
+
+  if (!startData.ok) {
+    throw new Error(startData.error);
+  }
+
+  const options = startData.data;
+  const credential = (await navigator.credentials.get({
+    publicKey: {
+      ...options,
+      challenge: fromBase64Url(options.challenge),
+      allowCredentials: options.allowCredentials.map((cred: CredentialDescriptor) => ({
+        ...cred,
+        id: fromBase64Url(cred.id),
+      })),
+    },
+  })) as PublicKeyCredential | null;
+
+  if (!credential) {
+    throw new Error("Passkey login cancelled");
+  }
+
+  const response = credential.response as AuthenticatorAssertionResponse;
+
+  const finishResp = await fetch(makeUrl("/api/auth/webauthn/login/finish"), {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      credential: {
+        id: credential.id,
+        rawId: toBase64Url(credential.rawId),
+        type: credential.type,
+        response: {
+          clientDataJSON: toBase64Url(response.clientDataJSON),
+          authenticatorData: toBase64Url(response.authenticatorData),
+          signature: toBase64Url(response.signature),
+        },
+      },
+    }),
+  });
+  const finishData = await finishResp.json();
+
+  if (!finishData.ok) {
+    throw new Error(finishData.error);
+  }
+
+  auth.token = finishData.data.token;
+}
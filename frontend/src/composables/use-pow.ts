@@ -0,0 +1,34 @@
+import type { PowChallenge } from "@/types/invites";
+
+export interface PowSolution {
+  algorithm: string;
+  challenge: string;
+  number: number;
+  salt: string;
+  signature: string;
+}
+
+async function sha256Hex(data: string): Promise<string> {
+  const digest = await crypto.subtle.digest("SHA-256", new TextEncoder().encode(data));
+
+  return [...new Uint8Array(digest)].map((byte) => byte.toString(16).padStart(2, "0")).join("");
+}
+
+/**
+ * Solve the proof-of-work challenge by finding the secret number.
+ */
+export async function solveChallenge(challenge: PowChallenge): Promise<PowSolution> {
+  for (let number = 0; number <= challenge.maxnumber; number++) {
+    if ((await sha256Hex(`${challenge.salt}${number}`)) === challenge.challenge) {
+      return {
+        algorithm: challenge.algorithm,
+        challenge: challenge.challenge,
+        number,
+        salt: challenge.salt,
+        signature: challenge.signature,
+      };
+    }
+  }
+
+  throw new Error("Failed to solve the proof-of-work challenge");
+}
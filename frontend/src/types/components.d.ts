@@ -7,6 +7,7 @@ export {}
 
 declare module 'vue' {
   export interface GlobalComponents {
+    ApiKeys: typeof import('./../components/ApiKeys.vue')['default']
     DarkToggle: typeof import('./../components/DarkToggle.vue')['default']
     ExpiryTime: typeof import('./../components/ExpiryTime.vue')['default']
     FooterInfo: typeof import('./../components/FooterInfo.vue')['default']
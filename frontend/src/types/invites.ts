@@ -8,13 +8,83 @@ export interface InviteOption {
   labelsExclude: string[] | null;
   sharedLibraries: InviteSharedLibrary | null;
   expiresAt: number | null;
+  notBefore?: number | null;
+  // seconds after the invite is first viewed
+  expiresAfterView?: number | null;
   roles: string[] | null;
+  emailDomainsAllow?: string[] | null;
+  emailDomainsDeny?: string[] | null;
+  boundEmail?: string | null;
+  generatePassword?: boolean | null;
+  // redemptions wait for an admin, answering the questions
+  requiresApproval?: boolean | null;
+  questions?: string[] | null;
+  powDifficulty?: number | null;
 }
 
 export interface Invite {
-  token: string;
+  // SHA-256 hash of the token, the token itself is only known right after creating it
+  id: string;
+  token?: string;
   option: InviteOption;
   user_id: string | null;
+  first_viewed_at?: number;
+  // only returned when opening the invite, `pending` until `notBefore`
+  status?: "active" | "pending";
+  // the effective expiry, including the one relative to the first view
+  expiresAt?: number | null;
+  // the masked bound email, only returned when opening the invite
+  boundEmail?: string;
+}
+
+export interface InviteApplication {
+  id: string;
+  inviteId: string;
+  option: InviteOption;
+  email: string;
+  answers: string[];
+  status: "pending" | "approved" | "rejected";
+  reason: string | null;
+  createdAt: number;
+  decidedAt: number | null;
+  userId: string | null;
+}
+
+// what the applicant see when checking their application
+export interface InviteApplicationStatus {
+  status: InviteApplication["status"];
+  reason: string | null;
+  createdAt: number;
+  decidedAt: number | null;
+  host: string | null;
+  passwordDeferred: boolean;
+  credentialsIssued: boolean;
+  // only returned once, the first time the approved application is checked
+  credentials: {
+    email: string;
+    password: string;
+  } | null;
+}
+
+export interface AccessRequest {
+  id: string;
+  email: string;
+  message: string;
+  // the requested library IDs
+  libraries: string[];
+  status: "pending" | "invited" | "rejected";
+  createdAt: number;
+  decidedAt: number | null;
+  inviteId: string | null;
+}
+
+// the public catalogue of the access request form
+export interface AccessRequestConfig {
+  enabled: boolean;
+  libraries: {
+    id: string;
+    name: string;
+  }[];
 }
 
 export interface InviteConfig {
@@ -25,3 +95,30 @@ export interface InviteConfig {
   }[];
   labels: string[];
 }
+
+export interface PowChallenge {
+  algorithm: string;
+  challenge: string;
+  maxnumber: number;
+  salt: string;
+  signature: string;
+}
+
+export type ApiKeyScope =
+  | "invite:create"
+  | "invite:read"
+  | "invite:delete"
+  | "config:read"
+  | "application:read"
+  | "application:review"
+  | "request:read"
+  | "request:review";
+
+export interface ApiKey {
+  id: string;
+  name: string;
+  scopes: ApiKeyScope[];
+  createdAt: number;
+  expiresAt: number | null;
+  lastUsedAt: number | null;
+}
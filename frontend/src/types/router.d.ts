@@ -43,6 +43,7 @@ declare module 'vue-router/auto/routes' {
     '/[...catchall]': RouteRecordInfo<'/[...catchall]', '/:catchall(.*)', { catchall: ParamValue<true> }, { catchall: ParamValue<false> }>,
     '/admin': RouteRecordInfo<'/admin', '/admin', Record<never, never>, Record<never, never>>,
     '/invite': RouteRecordInfo<'/invite', '/invite', Record<never, never>, Record<never, never>>,
+    '/request': RouteRecordInfo<'/request', '/request', Record<never, never>, Record<never, never>>,
   }
 }
 
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use tracing::error;
+
+use crate::crypto;
+
+pub(crate) const KLIBRARIAN_ACCESS_REQUESTS: &str = "k-librarian:access_requests";
+/// Held while the request is being decided.
+const KLIBRARIAN_ACCESS_REQUEST_DECIDING: &str = "k-librarian:access_request_deciding";
+
+/// Check if the public access request form is enabled, from `ACCESS_REQUESTS_ENABLED`.
+pub fn is_enabled() -> bool {
+    std::env::var("ACCESS_REQUESTS_ENABLED")
+        .map(|value| value.trim().to_lowercase())
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// The maximum number of pending requests from `ACCESS_REQUESTS_MAX_PENDING`, new ones are refused
+/// above it so the queue can't be flooded from many addresses.
+pub fn max_pending() -> usize {
+    std::env::var("ACCESS_REQUESTS_MAX_PENDING")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(100)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessRequestStatus {
+    Pending,
+    /// An invite was created for the request.
+    Invited,
+    Rejected,
+}
+
+/// Someone asking for access without an invite, waiting for an admin.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AccessRequest {
+    pub id: String,
+    pub email: String,
+    pub message: String,
+    /// The IDs of the requested Komga libraries.
+    pub libraries: Vec<String>,
+    pub status: AccessRequestStatus,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "decidedAt", default)]
+    pub decided_at: Option<u64>,
+    /// The hash of the invite created for the request.
+    #[serde(rename = "inviteId", default)]
+    pub invite_id: Option<String>,
+}
+
+impl AccessRequest {
+    pub fn new(email: String, message: String, libraries: Vec<String>) -> Self {
+        AccessRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            email,
+            message,
+            libraries,
+            status: AccessRequestStatus::Pending,
+            created_at: chrono::Utc::now().timestamp() as u64,
+            decided_at: None,
+            invite_id: None,
+        }
+    }
+
+    pub fn decide(&mut self, status: AccessRequestStatus, invite_id: Option<String>) {
+        self.status = status;
+        self.invite_id = invite_id;
+        self.decided_at = Some(chrono::Utc::now().timestamp() as u64);
+    }
+
+    fn to_stored(&self) -> String {
+        crypto::encrypt(&self.id, &serde_json::to_string(self).unwrap())
+    }
+
+    fn from_stored(id: &str, data: &str) -> Result<Self, anyhow::Error> {
+        let data = crypto::decrypt(id, data).map_err(|error| anyhow::anyhow!("{}", error))?;
+
+        Ok(serde_json::from_str(&data)?)
+    }
+}
+
+pub async fn save_access_request(
+    redis_conn: &mut MultiplexedConnection,
+    request: &AccessRequest,
+) -> Result<(), redis::RedisError> {
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_ACCESS_REQUESTS,
+            request.id.clone(),
+            request.to_stored(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_access_request(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<Option<AccessRequest>, redis::RedisError> {
+    let data: Option<String> = redis_conn.hget(KLIBRARIAN_ACCESS_REQUESTS, id).await?;
+
+    Ok(
+        data.and_then(|data| match AccessRequest::from_stored(id, &data) {
+            Ok(request) => Some(request),
+            Err(error) => {
+                error!("[{}] Failed to read access request: {}", id, error);
+                None
+            }
+        }),
+    )
+}
+
+/// Get every access request, the newest first.
+pub async fn get_access_requests(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<Vec<AccessRequest>, redis::RedisError> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(KLIBRARIAN_ACCESS_REQUESTS).await?;
+
+    let mut requests: Vec<AccessRequest> = all_keys
+        .iter()
+        .filter_map(|(id, data)| match AccessRequest::from_stored(id, data) {
+            Ok(request) => Some(request),
+            Err(error) => {
+                error!("[{}] Failed to read access request: {}", id, error);
+                None
+            }
+        })
+        .collect();
+    requests.sort_by_key(|request| std::cmp::Reverse(request.created_at));
+
+    Ok(requests)
+}
+
+/// Take the lock to decide the request, `false` if someone else is already deciding it.
+pub async fn lock_deciding(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<bool, redis::RedisError> {
+    let locked: Option<String> = redis::cmd("SET")
+        .arg(format!("{}:{}", KLIBRARIAN_ACCESS_REQUEST_DECIDING, id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(60)
+        .query_async(redis_conn)
+        .await?;
+
+    Ok(locked.is_some())
+}
+
+pub async fn unlock_deciding(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<(), redis::RedisError> {
+    let _: i32 = redis_conn
+        .del(format!("{}:{}", KLIBRARIAN_ACCESS_REQUEST_DECIDING, id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_access_request(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<bool, redis::RedisError> {
+    let deleted: i32 = redis_conn.hdel(KLIBRARIAN_ACCESS_REQUESTS, id).await?;
+
+    Ok(deleted > 0)
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+const KLIBRARIAN_API_KEYS: &str = "k-librarian:api_keys";
+const KLIBRARIAN_API_KEY_TOKENS: &str = "k-librarian:api_key_tokens";
+/// Prefix of every API key, used to tell them apart from session tokens.
+pub const API_KEY_PREFIX: &str = "klib_";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    #[serde(rename = "invite:create")]
+    InviteCreate,
+    #[serde(rename = "invite:read")]
+    InviteRead,
+    #[serde(rename = "invite:delete")]
+    InviteDelete,
+    #[serde(rename = "config:read")]
+    ConfigRead,
+    #[serde(rename = "application:read")]
+    ApplicationRead,
+    #[serde(rename = "application:review")]
+    ApplicationReview,
+    #[serde(rename = "request:read")]
+    RequestRead,
+    #[serde(rename = "request:review")]
+    RequestReview,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<u64>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<u64>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    fn is_expired(&self, current_unix: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if current_unix > expires_at)
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Create a new API key, returning the raw key and the stored info.
+///
+/// The raw key is only returned once, Redis only holds the hash of it.
+pub async fn create_api_key(
+    redis_conn: &mut MultiplexedConnection,
+    name: String,
+    scopes: Vec<Scope>,
+    expires_at: Option<u64>,
+) -> Result<(String, ApiKey), redis::RedisError> {
+    let token = format!(
+        "{}{}{}",
+        API_KEY_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let api_key = ApiKey {
+        id: uuid::Uuid::new_v4().to_string(),
+        name,
+        scopes,
+        created_at: chrono::Utc::now().timestamp() as u64,
+        expires_at,
+        last_used_at: None,
+    };
+
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_API_KEYS,
+            api_key.id.clone(),
+            serde_json::to_string(&api_key).unwrap(),
+        )
+        .await?;
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_API_KEY_TOKENS,
+            hash_token(&token),
+            api_key.id.clone(),
+        )
+        .await?;
+
+    Ok((token, api_key))
+}
+
+/// Verify the raw API key and record the last usage.
+///
+/// Returns `None` if the key is unknown, expired or revoked.
+pub async fn verify_api_key(
+    redis_conn: &mut MultiplexedConnection,
+    token: &str,
+) -> Result<Option<ApiKey>, redis::RedisError> {
+    let token_hash = hash_token(token);
+    let key_id: Option<String> = redis_conn
+        .hget(KLIBRARIAN_API_KEY_TOKENS, &token_hash)
+        .await?;
+
+    let key_id = match key_id {
+        Some(key_id) => key_id,
+        None => return Ok(None),
+    };
+
+    let data: Option<String> = redis_conn.hget(KLIBRARIAN_API_KEYS, &key_id).await?;
+    let mut api_key: ApiKey = match data.and_then(|data| serde_json::from_str(&data).ok()) {
+        Some(api_key) => api_key,
+        None => {
+            // The key got revoked, remove the dangling token
+            let _: i32 = redis_conn
+                .hdel(KLIBRARIAN_API_KEY_TOKENS, &token_hash)
+                .await
+                .unwrap_or(0);
+            return Ok(None);
+        }
+    };
+
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+    if api_key.is_expired(current_unix) {
+        return Ok(None);
+    }
+
+    api_key.last_used_at = Some(current_unix);
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_API_KEYS,
+            api_key.id.clone(),
+            serde_json::to_string(&api_key).unwrap(),
+        )
+        .await?;
+
+    Ok(Some(api_key))
+}
+
+pub async fn get_api_keys(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<Vec<ApiKey>, redis::RedisError> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(KLIBRARIAN_API_KEYS).await?;
+
+    let mut api_keys: Vec<ApiKey> = all_keys
+        .values()
+        .filter_map(|value| serde_json::from_str(value).ok())
+        .collect();
+
+    api_keys.sort_by_key(|api_key| api_key.created_at);
+    Ok(api_keys)
+}
+
+/// Revoke an API key by the ID, the key will be rejected on the next use.
+pub async fn revoke_api_key(
+    redis_conn: &mut MultiplexedConnection,
+    key_id: &str,
+) -> Result<bool, redis::RedisError> {
+    let removed: i32 = redis_conn.hdel(KLIBRARIAN_API_KEYS, key_id).await?;
+
+    Ok(removed > 0)
+}
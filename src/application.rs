@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use rand::RngCore;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::{bruteforce::constant_time_eq, crypto, routes::invite::InviteOption};
+
+pub(crate) const KLIBRARIAN_APPLICATIONS: &str = "k-librarian:applications";
+/// Held while the credentials of an approved application are handed out.
+const KLIBRARIAN_APPLICATION_ISSUING: &str = "k-librarian:application_issuing";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplicationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// A redemption of an invite that requires approval, waiting for an admin.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Application {
+    pub id: String,
+    /// The hash of the invite token that was redeemed.
+    #[serde(rename = "inviteId")]
+    pub invite_id: String,
+    pub option: InviteOption,
+    pub email: String,
+    /// The chosen password, only kept while pending and if encryption at rest is enabled.
+    ///
+    /// Without it the password is generated once approved, see `password_deferred`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Whether the password is generated and given to the applicant once approved.
+    #[serde(rename = "passwordDeferred", default)]
+    pub password_deferred: bool,
+    #[serde(default)]
+    pub answers: Vec<String>,
+    pub status: ApplicationStatus,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "decidedAt", default)]
+    pub decided_at: Option<u64>,
+    #[serde(rename = "userId", default)]
+    pub user_id: Option<String>,
+    /// Whether the generated credentials were already given to the applicant.
+    #[serde(rename = "credentialsIssued", default)]
+    pub credentials_issued: bool,
+    /// The SHA-256 of the secret the applicant use to check the status.
+    #[serde(rename = "secretHash")]
+    secret_hash: String,
+}
+
+/// The application as shown to the admins, without the secrets.
+#[derive(serde::Serialize)]
+pub struct ApplicationSummary<'a> {
+    id: &'a str,
+    #[serde(rename = "inviteId")]
+    invite_id: &'a str,
+    option: &'a InviteOption,
+    email: &'a str,
+    answers: &'a [String],
+    status: ApplicationStatus,
+    reason: Option<&'a str>,
+    #[serde(rename = "createdAt")]
+    created_at: u64,
+    #[serde(rename = "decidedAt")]
+    decided_at: Option<u64>,
+    #[serde(rename = "userId")]
+    user_id: Option<&'a str>,
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+impl Application {
+    /// Create a new pending application, returning it with the ticket given to the applicant.
+    pub fn new(
+        invite_id: String,
+        option: InviteOption,
+        email: String,
+        password: Option<String>,
+        answers: Vec<String>,
+    ) -> (Self, String) {
+        let mut secret = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let secret = format!("{:x}", Sha256::digest(secret));
+
+        // Never store the password in plaintext
+        let password = password.filter(|_| crypto::is_enabled());
+        let application = Application {
+            id: uuid::Uuid::new_v4().to_string(),
+            invite_id,
+            option,
+            email,
+            password_deferred: password.is_none(),
+            password,
+            answers,
+            status: ApplicationStatus::Pending,
+            reason: None,
+            created_at: chrono::Utc::now().timestamp() as u64,
+            decided_at: None,
+            user_id: None,
+            credentials_issued: false,
+            secret_hash: hash_secret(&secret),
+        };
+        let ticket = format!("{}.{}", application.id, secret);
+
+        (application, ticket)
+    }
+
+    pub fn summary(&self) -> ApplicationSummary<'_> {
+        ApplicationSummary {
+            id: &self.id,
+            invite_id: &self.invite_id,
+            option: &self.option,
+            email: &self.email,
+            answers: &self.answers,
+            status: self.status,
+            reason: self.reason.as_deref(),
+            created_at: self.created_at,
+            decided_at: self.decided_at,
+            user_id: self.user_id.as_deref(),
+        }
+    }
+
+    /// Record the decision, the password is not needed anymore after that.
+    pub fn decide(&mut self, status: ApplicationStatus, reason: Option<String>) {
+        self.status = status;
+        self.reason = reason;
+        self.decided_at = Some(chrono::Utc::now().timestamp() as u64);
+        self.password = None;
+    }
+
+    fn to_stored(&self) -> String {
+        crypto::encrypt(&self.id, &serde_json::to_string(self).unwrap())
+    }
+
+    fn from_stored(id: &str, data: &str) -> Result<Self, anyhow::Error> {
+        let data = crypto::decrypt(id, data).map_err(|error| anyhow::anyhow!("{}", error))?;
+
+        Ok(serde_json::from_str(&data)?)
+    }
+}
+
+pub async fn save_application(
+    redis_conn: &mut MultiplexedConnection,
+    application: &Application,
+) -> Result<(), redis::RedisError> {
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_APPLICATIONS,
+            application.id.clone(),
+            application.to_stored(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_application(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<Option<Application>, redis::RedisError> {
+    let data: Option<String> = redis_conn.hget(KLIBRARIAN_APPLICATIONS, id).await?;
+
+    Ok(
+        data.and_then(|data| match Application::from_stored(id, &data) {
+            Ok(application) => Some(application),
+            Err(error) => {
+                error!("[{}] Failed to read application: {}", id, error);
+                None
+            }
+        }),
+    )
+}
+
+/// Get the application from the applicant's ticket (`<id>.<secret>`).
+pub async fn get_application_by_ticket(
+    redis_conn: &mut MultiplexedConnection,
+    ticket: &str,
+) -> Result<Option<Application>, redis::RedisError> {
+    let (id, secret) = match ticket.split_once('.') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+
+    Ok(get_application(redis_conn, id)
+        .await?
+        .filter(|application| constant_time_eq(&application.secret_hash, &hash_secret(secret))))
+}
+
+/// Get every application, the newest first.
+pub async fn get_applications(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<Vec<Application>, redis::RedisError> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(KLIBRARIAN_APPLICATIONS).await?;
+
+    let mut applications: Vec<Application> = all_keys
+        .iter()
+        .filter_map(|(id, data)| match Application::from_stored(id, data) {
+            Ok(application) => Some(application),
+            Err(error) => {
+                error!("[{}] Failed to read application: {}", id, error);
+                None
+            }
+        })
+        .collect();
+    applications.sort_by_key(|application| std::cmp::Reverse(application.created_at));
+
+    Ok(applications)
+}
+
+pub async fn delete_application(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<bool, redis::RedisError> {
+    let deleted: i32 = redis_conn.hdel(KLIBRARIAN_APPLICATIONS, id).await?;
+
+    Ok(deleted > 0)
+}
+
+/// Take the lock to hand out the generated credentials, so only one request reset the password.
+pub async fn lock_issuing(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<bool, redis::RedisError> {
+    let locked: Option<String> = redis::cmd("SET")
+        .arg(format!("{}:{}", KLIBRARIAN_APPLICATION_ISSUING, id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(60)
+        .query_async(redis_conn)
+        .await?;
+
+    Ok(locked.is_some())
+}
+
+pub async fn unlock_issuing(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<(), redis::RedisError> {
+    let _: i32 = redis_conn
+        .del(format!("{}:{}", KLIBRARIAN_APPLICATION_ISSUING, id))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::net::IpAddr;
+
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+
+const KLIBRARIAN_LOGIN_FAILURES: &str = "k-librarian:login_failures";
+const KLIBRARIAN_LOGIN_LOCKOUT: &str = "k-librarian:login_lockout";
+/// The window where failed attempts are counted, reset after no failures for this long.
+const FAILURE_WINDOW: u64 = 60 * 60;
+const DEFAULT_MAX_ATTEMPTS: u64 = 5;
+const DEFAULT_GLOBAL_MAX_ATTEMPTS: u64 = 50;
+/// The first lockout duration, doubled on every failed attempt past the limit.
+const BASE_LOCKOUT: u64 = 30;
+const DEFAULT_MAX_LOCKOUT: u64 = 60 * 60;
+
+/// Compare two secrets in constant time, the length is hidden by comparing the hashes.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    Sha256::digest(a.as_bytes())
+        .ct_eq(&Sha256::digest(b.as_bytes()))
+        .into()
+}
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(default)
+}
+
+/// Get the lockout duration for the amount of failures, `None` if still below the limit.
+fn lockout_duration(failures: u64, max_attempts: u64) -> Option<u64> {
+    if failures < max_attempts {
+        return None;
+    }
+
+    let exponent = (failures - max_attempts).min(32) as u32;
+    let duration = BASE_LOCKOUT.saturating_mul(2u64.saturating_pow(exponent));
+
+    Some(duration.min(env_u64("LOGIN_MAX_LOCKOUT", DEFAULT_MAX_LOCKOUT)))
+}
+
+fn ip_key(prefix: &str, ip: IpAddr) -> String {
+    format!("{}:ip:{}", prefix, ip)
+}
+
+fn global_key(prefix: &str) -> String {
+    format!("{}:global", prefix)
+}
+
+/// Check if the IP (or everyone) is currently locked out.
+///
+/// Returns the amount of seconds until the lockout ends.
+pub async fn check_lockout(
+    redis_conn: &mut MultiplexedConnection,
+    ip: IpAddr,
+) -> Result<Option<u64>, redis::RedisError> {
+    let ip_ttl: i64 = redis_conn.ttl(ip_key(KLIBRARIAN_LOGIN_LOCKOUT, ip)).await?;
+    let global_ttl: i64 = redis_conn.ttl(global_key(KLIBRARIAN_LOGIN_LOCKOUT)).await?;
+
+    let remaining = ip_ttl.max(global_ttl);
+    if remaining > 0 {
+        Ok(Some(remaining as u64))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn count_failure(
+    redis_conn: &mut MultiplexedConnection,
+    failure_key: String,
+    lockout_key: String,
+    max_attempts: u64,
+) -> Result<Option<u64>, redis::RedisError> {
+    let failures: u64 = redis_conn.incr(&failure_key, 1).await?;
+    let _: i32 = redis_conn
+        .expire(&failure_key, FAILURE_WINDOW as i64)
+        .await?;
+
+    match lockout_duration(failures, max_attempts) {
+        Some(duration) => {
+            let _: () = redis_conn.set_ex(&lockout_key, failures, duration).await?;
+            Ok(Some(duration))
+        }
+        None => Ok(None),
+    }
+}
+
+fn log_failure(ip: IpAddr, event: &str, lockout: Option<u64>) {
+    match lockout {
+        Some(duration) => warn!(
+            target: "k_librarian::security",
+            ip = %ip,
+            event,
+            "Failed authentication attempt, locked out for {}s",
+            duration
+        ),
+        None => warn!(
+            target: "k_librarian::security",
+            ip = %ip,
+            event,
+            "Failed authentication attempt"
+        ),
+    }
+}
+
+/// Record a failed password attempt, returning the lockout duration if the IP is now locked.
+///
+/// The attempt also counts toward the global limit, to slow down guesses spread over many IPs.
+pub async fn record_failure(
+    redis_conn: &mut MultiplexedConnection,
+    ip: IpAddr,
+    event: &str,
+) -> Result<Option<u64>, redis::RedisError> {
+    let ip_lockout = count_failure(
+        redis_conn,
+        ip_key(KLIBRARIAN_LOGIN_FAILURES, ip),
+        ip_key(KLIBRARIAN_LOGIN_LOCKOUT, ip),
+        env_u64("LOGIN_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS),
+    )
+    .await?;
+    let global_lockout = count_failure(
+        redis_conn,
+        global_key(KLIBRARIAN_LOGIN_FAILURES),
+        global_key(KLIBRARIAN_LOGIN_LOCKOUT),
+        env_u64("LOGIN_GLOBAL_MAX_ATTEMPTS", DEFAULT_GLOBAL_MAX_ATTEMPTS),
+    )
+    .await?;
+
+    let lockout = ip_lockout.max(global_lockout);
+    log_failure(ip, event, lockout);
+
+    Ok(lockout)
+}
+
+/// Record a failed attempt that only counts against the IP, returning the lockout duration if
+/// the IP is now locked.
+///
+/// Used for the credentials that can't be guessed, so anonymous clients can't exhaust the
+/// global limit and lock the admin out.
+pub async fn record_ip_failure(
+    redis_conn: &mut MultiplexedConnection,
+    ip: IpAddr,
+    event: &str,
+) -> Result<Option<u64>, redis::RedisError> {
+    let lockout = count_failure(
+        redis_conn,
+        ip_key(KLIBRARIAN_LOGIN_FAILURES, ip),
+        ip_key(KLIBRARIAN_LOGIN_LOCKOUT, ip),
+        env_u64("LOGIN_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS),
+    )
+    .await?;
+    log_failure(ip, event, lockout);
+
+    Ok(lockout)
+}
+
+/// Record a successful login, the failures of the IP are forgiven.
+pub async fn record_success(
+    redis_conn: &mut MultiplexedConnection,
+    ip: IpAddr,
+    event: &str,
+) -> Result<(), redis::RedisError> {
+    info!(
+        target: "k_librarian::security",
+        ip = %ip,
+        event,
+        "Successful authentication"
+    );
+
+    let _: i32 = redis_conn
+        .del(ip_key(KLIBRARIAN_LOGIN_FAILURES, ip))
+        .await?;
+
+    Ok(())
+}
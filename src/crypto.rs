@@ -0,0 +1,368 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+/// The prefix of the encrypted records, anything else is stored as plaintext.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_SIZE: usize = 24;
+
+#[derive(Debug)]
+pub enum CryptoError {
+    InvalidKey(String),
+    UnknownKey(String),
+    Decrypt,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::InvalidKey(reason) => write!(f, "Invalid encryption key: {}", reason),
+            CryptoError::UnknownKey(key_id) => write!(
+                f,
+                "Encrypted with an unknown key ({}), is the encryption key missing?",
+                key_id
+            ),
+            CryptoError::Decrypt => write!(f, "Failed to decrypt, is the encryption key wrong?"),
+        }
+    }
+}
+
+struct Key {
+    /// A short fingerprint of the key, stored with the record to find the key on rotation.
+    id: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    /// Parse a `base64:` or `hex:` encoded key, the encoding must be explicit so a passphrase
+    /// that happens to be 32 characters long is never used as the raw key.
+    fn parse(value: &[u8]) -> Result<Self, CryptoError> {
+        let value = String::from_utf8_lossy(value).trim().to_string();
+
+        let key = if let Some(encoded) = value.strip_prefix("base64:") {
+            STANDARD
+                .decode(encoded.trim())
+                .map_err(|_| CryptoError::InvalidKey("not valid base64".to_string()))?
+        } else if let Some(encoded) = value.strip_prefix("hex:") {
+            let encoded = encoded.trim();
+            if encoded.len() % 2 != 0 || !encoded.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(CryptoError::InvalidKey("not valid hex".to_string()));
+            }
+
+            (0..encoded.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).unwrap())
+                .collect()
+        } else {
+            return Err(CryptoError::InvalidKey(
+                "the key must start with `base64:` or `hex:`".to_string(),
+            ));
+        };
+
+        if key.len() != 32 {
+            return Err(CryptoError::InvalidKey(format!(
+                "expected 32 bytes, got {}",
+                key.len()
+            )));
+        }
+
+        let id = format!("{:x}", Sha256::digest(&key))[..8].to_string();
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|error| CryptoError::InvalidKey(error.to_string()))?;
+
+        Ok(Key { id, cipher })
+    }
+}
+
+struct Keyring {
+    current: Option<Key>,
+    /// Previous keys, only used to decrypt the records until they are rotated.
+    old: Vec<Key>,
+}
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Load the encryption keys from the environment.
+///
+/// The key is read from `ENCRYPTION_KEY` or from the file at `ENCRYPTION_KEY_FILE`, previous
+/// keys can be kept in `ENCRYPTION_OLD_KEYS` until every record is re-encrypted.
+pub fn init() -> Result<bool, CryptoError> {
+    let get_env = |key: &str| {
+        std::env::var(key)
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let current = match (get_env("ENCRYPTION_KEY"), get_env("ENCRYPTION_KEY_FILE")) {
+        (Some(key), _) => Some(Key::parse(key.as_bytes())?),
+        (None, Some(path)) => {
+            let data = std::fs::read(&path).map_err(|error| {
+                CryptoError::InvalidKey(format!("failed to read {}: {}", path, error))
+            })?;
+            Some(Key::parse(&data)?)
+        }
+        (None, None) => None,
+    };
+
+    let old = get_env("ENCRYPTION_OLD_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|key| key.trim())
+        .filter(|key| !key.is_empty())
+        .map(|key| Key::parse(key.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let enabled = current.is_some();
+    KEYRING.get_or_init(|| Keyring { current, old });
+
+    Ok(enabled)
+}
+
+fn keyring() -> &'static Keyring {
+    KEYRING.get_or_init(|| Keyring {
+        current: None,
+        old: vec![],
+    })
+}
+
+/// Encrypt the record with the current key, the `context` (e.g. the record key) is
+/// authenticated so the record can't be moved somewhere else.
+///
+/// The record is returned as is if encryption is disabled.
+pub fn encrypt(context: &str, plaintext: &str) -> String {
+    match &keyring().current {
+        Some(key) => seal(key, context, plaintext),
+        None => plaintext.to_string(),
+    }
+}
+
+fn seal(key: &Key, context: &str, plaintext: &str) -> String {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: context.as_bytes(),
+            },
+        )
+        .expect("encryption never fails");
+
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+
+    format!("{}{}:{}", ENCRYPTED_PREFIX, key.id, STANDARD.encode(data))
+}
+
+/// Decrypt the record with whichever key encrypted it, plaintext records are returned as is.
+pub fn decrypt(context: &str, stored: &str) -> Result<String, CryptoError> {
+    let keyring = keyring();
+
+    open(
+        keyring.current.iter().chain(keyring.old.iter()),
+        context,
+        stored,
+    )
+}
+
+fn open<'a>(
+    mut keys: impl Iterator<Item = &'a Key>,
+    context: &str,
+    stored: &str,
+) -> Result<String, CryptoError> {
+    let encrypted = match stored.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(encrypted) => encrypted,
+        None => return Ok(stored.to_string()),
+    };
+
+    let (key_id, data) = encrypted.split_once(':').ok_or(CryptoError::Decrypt)?;
+    let key = keys
+        .find(|key| key.id == key_id)
+        .ok_or(CryptoError::UnknownKey(key_id.to_string()))?;
+
+    let data = STANDARD.decode(data).map_err(|_| CryptoError::Decrypt)?;
+    if data.len() < NONCE_SIZE {
+        return Err(CryptoError::Decrypt);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+
+    let plaintext = key
+        .cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: context.as_bytes(),
+            },
+        )
+        .map_err(|_| CryptoError::Decrypt)?;
+
+    String::from_utf8(plaintext).map_err(|_| CryptoError::Decrypt)
+}
+
+/// Check if the records are encrypted at rest.
+pub fn is_enabled() -> bool {
+    keyring().current.is_some()
+}
+
+/// Check if the record is stored the way the current configuration wants, otherwise it
+/// need to be re-encrypted (or decrypted if encryption got disabled).
+pub fn is_current(stored: &str) -> bool {
+    match (&keyring().current, stored.strip_prefix(ENCRYPTED_PREFIX)) {
+        (Some(key), Some(encrypted)) => encrypted.split(':').next() == Some(key.id.as_str()),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Make sure every record of the Redis hash can be read with the configured keys.
+///
+/// The records must be encrypted with their field as the context.
+pub async fn check_hash(
+    redis_conn: &mut MultiplexedConnection,
+    hash: &str,
+) -> Result<(), anyhow::Error> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(hash).await?;
+
+    for (id, value) in all_keys {
+        decrypt(&id, &value).map_err(|error| anyhow::anyhow!("[{}] {}", id, error))?;
+    }
+
+    Ok(())
+}
+
+/// Re-encrypt the records of the Redis hash that are not stored with the current key (or
+/// decrypt them if the encryption got disabled), used on key rotation.
+pub async fn rotate_hash(
+    redis_conn: &mut MultiplexedConnection,
+    hash: &str,
+) -> Result<usize, anyhow::Error> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(hash).await?;
+
+    let mut rotated = 0;
+    for (id, value) in all_keys {
+        if is_current(&value) {
+            continue;
+        }
+
+        let plaintext =
+            decrypt(&id, &value).map_err(|error| anyhow::anyhow!("[{}] {}", id, error))?;
+        // Only replace the record if nobody touched it in the meantime
+        let replaced: i32 = redis::Script::new(
+            r"if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+                return redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+            end
+            return -1",
+        )
+        .key(hash)
+        .arg(&id)
+        .arg(&value)
+        .arg(encrypt(&id, &plaintext))
+        .invoke_async(redis_conn)
+        .await?;
+        if replaced >= 0 {
+            rotated += 1;
+        }
+    }
+
+    Ok(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_HEX: &str = "hex:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_BASE64: &str = "base64:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const OTHER_KEY: &str = "base64:ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8=";
+
+    fn key(value: &str) -> Key {
+        Key::parse(value.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn key_encoding_must_be_explicit() {
+        // both encodings of the same key
+        assert_eq!(key(KEY_HEX).id, key(KEY_BASE64).id);
+        assert_eq!(key(&format!(" {}\n", KEY_BASE64)).id, key(KEY_BASE64).id);
+
+        // a 32 characters passphrase is not a raw key
+        assert!(Key::parse(b"correct horse battery staple 123").is_err());
+        assert!(Key::parse(b"AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").is_err());
+        assert!(Key::parse(b"hex:zz").is_err());
+        assert!(Key::parse(b"hex:0001").is_err());
+        assert!(Key::parse(b"base64:AAEC").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = key(KEY_BASE64);
+        let stored = seal(&key, "context", "secret data");
+
+        assert!(stored.starts_with(ENCRYPTED_PREFIX));
+        assert!(!stored.contains("secret data"));
+        assert_eq!(
+            open([&key].into_iter(), "context", &stored).unwrap(),
+            "secret data"
+        );
+        // plaintext records are read as is
+        assert_eq!(
+            open([&key].into_iter(), "context", "plain").unwrap(),
+            "plain"
+        );
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let key = key(KEY_BASE64);
+        let other = self::key(OTHER_KEY);
+        let stored = seal(&key, "context", "secret data");
+
+        assert!(matches!(
+            open([&other].into_iter(), "context", &stored),
+            Err(CryptoError::UnknownKey(_))
+        ));
+
+        // a record claiming to be from the other key
+        let forged = stored.replacen(&key.id, &other.id, 1);
+        assert!(matches!(
+            open([&key, &other].into_iter(), "context", &forged),
+            Err(CryptoError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn context_mismatch_is_rejected() {
+        let key = key(KEY_BASE64);
+        let stored = seal(&key, "invite-a", "secret data");
+
+        assert!(matches!(
+            open([&key].into_iter(), "invite-b", &stored),
+            Err(CryptoError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn tampered_record_is_rejected() {
+        let key = key(KEY_BASE64);
+        let stored = seal(&key, "context", "secret data");
+        let (prefix, data) = stored.rsplit_once(':').unwrap();
+        let mut data = STANDARD.decode(data).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let tampered = format!("{}:{}", prefix, STANDARD.encode(data));
+
+        assert!(matches!(
+            open([&key].into_iter(), "context", &tampered),
+            Err(CryptoError::Decrypt)
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::HashSet,
+    sync::{OnceLock, RwLock},
+    time::SystemTime,
+};
+
+/// Normalize the email address for comparison, trimmed and lowercased.
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Check if both email addresses are the same, ignoring case and surrounding whitespace.
+pub fn matches(email: &str, other: &str) -> bool {
+    normalize(email) == normalize(other)
+}
+
+/// Check if the email address is valid, with the same rules as the invite form.
+pub fn is_valid(email: &str) -> bool {
+    #[derive(garde::Validate)]
+    struct Address<'a>(#[garde(email)] &'a str);
+
+    garde::Validate::validate(&Address(email), &()).is_ok()
+}
+
+/// Mask the email address for showing it to someone who only know the invite token,
+/// e.g. `jane.doe@example.org` becomes `j***@e***.org`.
+pub fn mask(email: &str) -> String {
+    let email = normalize(email);
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return "***".to_string(),
+    };
+
+    let mask_part = |part: &str| match part.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => "***".to_string(),
+    };
+
+    let masked_domain = match domain.rsplit_once('.') {
+        Some((name, tld)) => format!("{}.{}", mask_part(name), tld),
+        None => mask_part(domain),
+    };
+
+    format!("{}@{}", mask_part(local), masked_domain)
+}
+
+/// The disposable email domains shipped with Librarian.
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+#[derive(Debug)]
+pub enum DomainError {
+    NotAllowed(String),
+    Denied(String),
+    Disposable(String),
+}
+
+impl std::fmt::Display for DomainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomainError::NotAllowed(domain) => {
+                write!(f, "Email addresses from {} are not allowed here", domain)
+            }
+            DomainError::Denied(domain) => {
+                write!(f, "Email addresses from {} are not allowed", domain)
+            }
+            DomainError::Disposable(domain) => {
+                write!(f, "Disposable email addresses ({}) are not allowed", domain)
+            }
+        }
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain
+        .trim()
+        .trim_start_matches('@')
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+/// Parse a list of domains separated by commas or lines, `#` starts a comment.
+fn parse_domains(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(','))
+        .map(normalize_domain)
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// Check if the domain is in the list, subdomains included.
+fn matches_domain(domain: &str, list: &[String]) -> bool {
+    list.iter().any(|entry| {
+        let entry = normalize_domain(entry);
+        domain == entry || domain.ends_with(&format!(".{}", entry))
+    })
+}
+
+fn domain_list(name: &str) -> Vec<String> {
+    parse_domains(&std::env::var(name).unwrap_or_default())
+}
+
+fn global_allowlist() -> &'static [String] {
+    static ALLOWLIST: OnceLock<Vec<String>> = OnceLock::new();
+
+    ALLOWLIST.get_or_init(|| domain_list("EMAIL_DOMAIN_ALLOWLIST"))
+}
+
+fn global_denylist() -> &'static [String] {
+    static DENYLIST: OnceLock<Vec<String>> = OnceLock::new();
+
+    DENYLIST.get_or_init(|| domain_list("EMAIL_DOMAIN_DENYLIST"))
+}
+
+struct DisposableDomains {
+    /// The modification time of `DISPOSABLE_EMAIL_DOMAINS_FILE` when it was loaded.
+    modified: Option<SystemTime>,
+    domains: HashSet<String>,
+}
+
+/// Check if the domain is a disposable one, `BLOCK_DISPOSABLE_EMAILS=false` disable it.
+///
+/// The list from `DISPOSABLE_EMAIL_DOMAINS_FILE` is reloaded whenever the file changes.
+fn is_disposable(domain: &str) -> bool {
+    static DISPOSABLE: OnceLock<RwLock<DisposableDomains>> = OnceLock::new();
+
+    let enabled = std::env::var("BLOCK_DISPOSABLE_EMAILS")
+        .map(|value| value.trim().to_lowercase())
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true);
+    if !enabled {
+        return false;
+    }
+
+    let path = std::env::var("DISPOSABLE_EMAIL_DOMAINS_FILE")
+        .ok()
+        .filter(|path| !path.trim().is_empty());
+    let modified = path
+        .as_ref()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .and_then(|metadata| metadata.modified().ok());
+
+    let lock = DISPOSABLE.get_or_init(|| {
+        RwLock::new(DisposableDomains {
+            modified: None,
+            domains: HashSet::new(),
+        })
+    });
+    let outdated = {
+        let disposable = lock.read().unwrap();
+        disposable.domains.is_empty() || disposable.modified != modified
+    };
+    if outdated {
+        let mut domains: HashSet<String> = parse_domains(BUNDLED_DISPOSABLE_DOMAINS)
+            .into_iter()
+            .collect();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(data) => domains.extend(parse_domains(&data)),
+                Err(error) => tracing::warn!("Failed to read {}: {}", path, error),
+            }
+        }
+
+        *lock.write().unwrap() = DisposableDomains { modified, domains };
+    }
+
+    let disposable = lock.read().unwrap();
+    // Check the domain and every parent domain
+    let mut candidate = domain;
+    loop {
+        if disposable.domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+/// Check the domain of the email address against the allowlists and denylists.
+///
+/// The invite allowlist replaces the global one, while both denylists apply. A domain that is
+/// explicitly allowed is never considered disposable.
+pub fn check_domain(
+    email: &str,
+    allowlist: Option<&[String]>,
+    denylist: Option<&[String]>,
+) -> Result<(), DomainError> {
+    let domain = normalize_domain(
+        normalize(email)
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default(),
+    );
+
+    if matches_domain(&domain, global_denylist())
+        || denylist.is_some_and(|denylist| matches_domain(&domain, denylist))
+    {
+        return Err(DomainError::Denied(domain));
+    }
+
+    let allowlist = allowlist
+        .filter(|allowlist| !allowlist.is_empty())
+        .unwrap_or(global_allowlist());
+    if !allowlist.is_empty() {
+        return match matches_domain(&domain, allowlist) {
+            true => Ok(()),
+            false => Err(DomainError::NotAllowed(domain)),
+        };
+    }
+
+    if is_disposable(&domain) {
+        return Err(DomainError::Disposable(domain));
+    }
+
+    Ok(())
+}
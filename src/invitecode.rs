@@ -0,0 +1,353 @@
+use rand::Rng;
+
+use crate::signedlink;
+
+/// Crockford's base32 alphabet, without the easily confused I, L, O and U.
+const BASE32_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const DEFAULT_MIN_ENTROPY: u32 = 40;
+/// The vanity slugs that would collide with the invite routes, e.g. `GET /api/invite/config`.
+const RESERVED_SLUGS: [&str; 5] = ["apply", "cards", "challenge", "config", "qr"];
+/// How many times to retry when a generated code is already taken.
+pub const MAX_GENERATE_ATTEMPTS: usize = 5;
+
+/// 256 short and distinct words, each word is 8 bits of entropy.
+pub const WORDLIST: [&str; 256] = [
+    "acorn", "alpine", "amber", "anchor", "apple", "arrow", "aspen", "atlas", "autumn", "badge",
+    "bamboo", "banner", "basil", "bay", "beacon", "bear", "berry", "birch", "bison", "blaze",
+    "blossom", "bonfire", "bramble", "breeze", "brick", "brook", "bubble", "cabin", "cactus",
+    "camel", "candle", "canvas", "canyon", "cargo", "castle", "cedar", "chalk", "charm", "cherry",
+    "cider", "cinder", "circle", "citrus", "clay", "cliff", "cloud", "clover", "cobalt", "cocoa",
+    "comet", "copper", "coral", "cotton", "cove", "crane", "crater", "crest", "crystal", "cypress",
+    "daisy", "dawn", "delta", "desert", "dolphin", "dragon", "drift", "dune", "dusk", "eagle",
+    "echo", "elm", "ember", "emerald", "fable", "falcon", "feather", "fern", "fiddle", "field",
+    "fig", "fjord", "flame", "flint", "forest", "fossil", "fountain", "fox", "frost", "galaxy",
+    "gale", "garden", "garnet", "gem", "geyser", "ginger", "glacier", "glade", "globe", "granite",
+    "grove", "gull", "harbor", "harvest", "hawk", "hazel", "heath", "heron", "hickory", "hollow",
+    "honey", "horizon", "ink", "iris", "island", "ivory", "jade", "jasmine", "jasper", "jungle",
+    "juniper", "kayak", "kelp", "kite", "kiwi", "koala", "lagoon", "lake", "lantern", "lark",
+    "lava", "leaf", "lemon", "lilac", "lily", "lime", "linen", "lotus", "lunar", "lynx", "mango",
+    "maple", "marble", "meadow", "melon", "mesa", "meteor", "mint", "mirror", "mist", "misty",
+    "moon", "moss", "mountain", "nectar", "nest", "nutmeg", "oak", "oasis", "ocean", "olive",
+    "onyx", "opal", "orbit", "orchid", "otter", "owl", "oyster", "palm", "panda", "paper",
+    "pastel", "peach", "pearl", "pebble", "pepper", "pilot", "pine", "planet", "plum", "polar",
+    "pond", "poppy", "prairie", "prism", "puffin", "quartz", "quest", "quill", "rabbit", "rain",
+    "rapid", "raven", "reef", "ridge", "ripple", "river", "robin", "rocket", "rose", "ruby",
+    "saffron", "sage", "sail", "salmon", "sand", "sapphire", "satin", "scarlet", "seed", "shadow",
+    "shell", "sierra", "silver", "sky", "slate", "snow", "spark", "sparrow", "spice", "spring",
+    "spruce", "squirrel", "star", "stone", "storm", "stream", "summit", "sun", "swan", "swift",
+    "teal", "thistle", "thunder", "thyme", "tiger", "timber", "topaz", "trail", "tulip", "tundra",
+    "turtle", "valley", "velvet", "vine", "violet", "vista", "walnut", "wave", "wheat", "willow",
+    "winter", "wolf", "wren", "yarrow", "zebra", "zephyr",
+];
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenFormat {
+    /// A random UUID v4, the default.
+    #[default]
+    Uuid,
+    /// Base32 groups with a check digit, e.g. `7KQ2-MZ9X-4HCT`.
+    Short,
+    /// Dash separated words, e.g. `amber-river-lantern-maple-otter`.
+    Words,
+}
+
+/// The minimum entropy (in bits) of the generated codes, from `INVITE_MIN_ENTROPY`.
+fn min_entropy() -> u32 {
+    std::env::var("INVITE_MIN_ENTROPY")
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(DEFAULT_MIN_ENTROPY)
+}
+
+fn base32_value(c: char) -> Option<u32> {
+    // Crockford's decoding is lenient with the confusable letters
+    let c = match c.to_ascii_uppercase() {
+        'I' | 'L' => '1',
+        'O' => '0',
+        c => c,
+    };
+
+    BASE32_ALPHABET
+        .iter()
+        .position(|a| *a as char == c)
+        .map(|value| value as u32)
+}
+
+/// Luhn mod 32 check character of the values, catch single typos and swapped neighbours.
+fn check_value(values: &[u32]) -> u32 {
+    let mut factor = 2;
+    let mut sum = 0;
+
+    for value in values.iter().rev() {
+        let addend = factor * value;
+        sum += addend / 32 + addend % 32;
+        factor = if factor == 2 { 1 } else { 2 };
+    }
+
+    (32 - sum % 32) % 32
+}
+
+fn group(chars: &[char]) -> String {
+    chars
+        .chunks(4)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// How many random base32 characters give at least `min_entropy` bits, padded so the code
+/// and its check digit fill up the groups of 4.
+fn short_length(min_entropy: u32) -> usize {
+    let mut length = min_entropy.div_ceil(5) as usize;
+    while !(length + 1).is_multiple_of(4) {
+        length += 1;
+    }
+
+    length
+}
+
+/// How many words give at least `min_entropy` bits, never less than 3.
+fn words_count(min_entropy: u32) -> u32 {
+    min_entropy.div_ceil(8).max(3)
+}
+
+fn generate_short(min_entropy: u32) -> String {
+    let length = short_length(min_entropy);
+    let mut rng = rand::thread_rng();
+    let values: Vec<u32> = (0..length).map(|_| rng.gen_range(0..32)).collect();
+
+    let mut chars: Vec<char> = values
+        .iter()
+        .map(|value| BASE32_ALPHABET[*value as usize] as char)
+        .collect();
+    chars.push(BASE32_ALPHABET[check_value(&values) as usize] as char);
+
+    group(&chars)
+}
+
+fn generate_words(min_entropy: u32) -> String {
+    let count = words_count(min_entropy);
+    let mut rng = rand::thread_rng();
+
+    (0..count)
+        .map(|_| WORDLIST[rng.gen_range(0..WORDLIST.len())])
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Generate a new invite token in the format.
+pub fn generate(format: TokenFormat) -> String {
+    match format {
+        TokenFormat::Uuid => uuid::Uuid::new_v4().to_string(),
+        TokenFormat::Short => generate_short(min_entropy()),
+        TokenFormat::Words => generate_words(min_entropy()),
+    }
+}
+
+/// Parse a short code typed by a human (any case, with or without dashes), `None` if it is
+/// not a valid short code.
+fn parse_short(token: &str) -> Option<String> {
+    let chars: Vec<char> = token
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+    if chars.len() < 8 || !chars.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let values = chars
+        .iter()
+        .map(|c| base32_value(*c))
+        .collect::<Option<Vec<u32>>>()?;
+    let (check, values) = values.split_last()?;
+    if check_value(values) != *check {
+        return None;
+    }
+
+    let canonical: Vec<char> = values
+        .iter()
+        .chain(std::iter::once(check))
+        .map(|value| BASE32_ALPHABET[*value as usize] as char)
+        .collect();
+
+    Some(group(&canonical))
+}
+
+/// The canonical form of the token, so `7kq2 mz9x 4hct` and `7KQ2-MZ9X-4HCT` are the same invite.
+pub fn canonical(token: &str) -> String {
+    let token = token.trim();
+
+    // The signed invites are base64, which is case sensitive
+    if signedlink::is_signed(token) {
+        return token.to_string();
+    }
+
+    parse_short(token).unwrap_or_else(|| {
+        token
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase()
+    })
+}
+
+/// Validate an admin chosen vanity slug, e.g. `bookclub-2026`.
+pub fn validate_slug(slug: &str) -> Result<String, &'static str> {
+    let slug = slug.trim().to_lowercase();
+
+    if slug.len() < 3 || slug.len() > 64 {
+        return Err("The invite code must be between 3 and 64 characters");
+    }
+    if !slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err("The invite code can only contain letters, digits and dashes");
+    }
+    if slug.starts_with('-') || slug.ends_with('-') {
+        return Err("The invite code can't start or end with a dash");
+    }
+    if RESERVED_SLUGS.contains(&slug.as_str()) {
+        return Err("The invite code is reserved, please choose another one");
+    }
+
+    Ok(slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(code: &str) -> Vec<u32> {
+        code.chars()
+            .filter(|c| *c != '-')
+            .map(|c| base32_value(c).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn check_digit() {
+        assert_eq!(check_value(&[]), 0);
+        assert_eq!(check_value(&[0, 0, 0]), 0);
+        // 1 is doubled: 2, the check brings the sum to 32
+        assert_eq!(check_value(&[1]), 30);
+        // 31 * 2 = 62 = 1 * 32 + 30, digits summed to 31
+        assert_eq!(check_value(&[31]), 1);
+        assert_eq!(check_value(&[1, 1]), 29);
+    }
+
+    #[test]
+    fn generated_short_codes_are_valid() {
+        for _ in 0..100 {
+            let code = generate_short(40);
+
+            assert_eq!(code.len(), 14);
+            assert_eq!(parse_short(&code), Some(code.clone()));
+            assert_eq!(canonical(&code), code);
+        }
+    }
+
+    #[test]
+    fn typos_are_detected() {
+        let code = generate_short(40);
+        let chars: Vec<char> = code.chars().filter(|c| *c != '-').collect();
+
+        // every single character substitution
+        for idx in 0..chars.len() {
+            for replacement in BASE32_ALPHABET.iter().map(|c| *c as char) {
+                if replacement == chars[idx] {
+                    continue;
+                }
+
+                let mut typo = chars.clone();
+                typo[idx] = replacement;
+                assert_eq!(parse_short(&typo.iter().collect::<String>()), None);
+            }
+        }
+
+        // every swap of two different neighbours, except 0 and Z which Luhn can't tell apart
+        for idx in 0..chars.len() - 1 {
+            let pair = [chars[idx], chars[idx + 1]];
+            if pair[0] == pair[1] || pair == ['0', 'Z'] || pair == ['Z', '0'] {
+                continue;
+            }
+
+            let mut swapped = chars.clone();
+            swapped.swap(idx, idx + 1);
+            let swapped: String = swapped.iter().collect();
+            assert_eq!(parse_short(&swapped), None, "{} -> {}", code, swapped);
+        }
+    }
+
+    #[test]
+    fn short_codes_are_canonicalised() {
+        let code = "7KQ2-MZ9X-4HCT";
+        let check = BASE32_ALPHABET[check_value(&values("7KQ2MZ9X4HC")) as usize] as char;
+        let code = format!("{}{}", &code[..13], check);
+
+        assert_eq!(canonical(&code.to_lowercase()), code);
+        assert_eq!(canonical(&code.replace('-', " ")), code);
+        assert_eq!(canonical(&code.replace('-', "")), code);
+        // the confusable letters are read as digits
+        let confusable = code.replace('1', "l").replace('0', "O");
+        assert_eq!(canonical(&confusable), code);
+    }
+
+    #[test]
+    fn other_tokens_are_canonicalised() {
+        assert_eq!(canonical("  Amber River  Lantern "), "amber-river-lantern");
+        assert_eq!(canonical("BookClub-2026"), "bookclub-2026");
+        assert_eq!(
+            canonical("5A0B9C4E-1D2F-4A3B-8C7D-6E5F4A3B2C1D"),
+            "5a0b9c4e-1d2f-4a3b-8c7d-6e5f4a3b2c1d"
+        );
+        // signed invites are case sensitive
+        assert_eq!(canonical(" s1.AbC.dEf "), "s1.AbC.dEf");
+    }
+
+    #[test]
+    fn entropy() {
+        for min_entropy in [1, 20, 40, 64, 80, 128] {
+            let length = short_length(min_entropy);
+            assert!(length as u32 * 5 >= min_entropy);
+            assert_eq!((length + 1) % 4, 0);
+            // rounded up to a character, then at most 3 characters of padding
+            assert!((length as u32) * 5 < min_entropy + 5 + 15);
+
+            let count = words_count(min_entropy);
+            assert!(count * 8 >= min_entropy);
+            assert!(count >= 3);
+            assert_eq!(generate_words(min_entropy).split('-').count() as u32, count);
+        }
+
+        assert_eq!(short_length(40), 11);
+        assert_eq!(words_count(40), 5);
+        assert_eq!(WORDLIST.len(), 256);
+        let mut words = WORDLIST.to_vec();
+        words.sort();
+        words.dedup();
+        assert_eq!(words.len(), 256);
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(
+            validate_slug(" BookClub-2026 "),
+            Ok("bookclub-2026".to_string())
+        );
+        assert!(validate_slug("ab").is_err());
+        assert!(validate_slug(&"a".repeat(65)).is_err());
+        assert!(validate_slug("book club").is_err());
+        assert!(validate_slug("-bookclub").is_err());
+        assert!(validate_slug("bookclub-").is_err());
+    }
+
+    #[test]
+    fn reserved_slugs_are_rejected() {
+        for slug in ["config", "cards", "challenge", "apply", "qr", "Config"] {
+            assert!(validate_slug(slug).is_err(), "{}", slug);
+        }
+    }
+}
@@ -181,6 +181,22 @@ impl KomgaClient {
         }
     }
 
+    pub async fn update_user_password(&self, user_id: &str, password: &str) -> anyhow::Result<()> {
+        let res = self
+            .client
+            .patch(format!("{}/api/v2/users/{}/password", self.url, user_id))
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&serde_json::json!({ "password": password }))
+            .send()
+            .await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Failed to update user password"))
+        }
+    }
+
     pub async fn get_sharing_labels(&self) -> anyhow::Result<Vec<String>> {
         let res = self
             .client
@@ -1,34 +1,74 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::State,
+    extract::{Request, State},
     http::Uri,
+    middleware,
     response::{Html, IntoResponse, Redirect},
     routing::get,
     Router,
 };
 use komga::KomgaClient;
+use proxy::ClientIp;
 use tokio::net::TcpListener;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    services::ServeDir,
-    trace::TraceLayer,
-};
+use tower_http::{services::ServeDir, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use urlencoding::encode;
 
 include!(concat!(env!("OUT_DIR"), "/index_html.rs"));
 
+mod accessrequest;
+mod apikey;
+mod application;
+mod bruteforce;
+mod crypto;
+mod email;
+mod invitecode;
 mod komga;
+mod mailer;
+mod oidc;
+mod password;
+mod pow;
+mod proxy;
+mod qr;
+mod ratelimit;
 mod routes;
+mod security;
+mod session;
+mod signedlink;
+mod totp;
+mod verification;
+mod webauthn;
 
 #[derive(Clone)]
 pub struct AppState {
     pub redis: Arc<redis::Client>,
 }
 
+/// The Redis hashes whose records are encrypted at rest, keyed by the record ID.
+const ENCRYPTED_HASHES: [&str; 4] = [
+    routes::invite::KLIBRARIAN_INVITE_TOKEN,
+    routes::invite::KLIBRARIAN_APPLICATION_USERS,
+    application::KLIBRARIAN_APPLICATIONS,
+    accessrequest::KLIBRARIAN_ACCESS_REQUESTS,
+];
+
 #[tokio::main]
 async fn main() {
+    // Commands that run without the server, keep the stdout clean for them
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("mint-invite") {
+        dotenv::dotenv().ok();
+
+        match signedlink::cli(&args[2..]) {
+            Ok(_) => std::process::exit(0),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -80,10 +120,89 @@ async fn main() {
     tracing::info!("🔌 Connecting to Redis at: {}", redis_url);
     let redis_client = redis::Client::open(redis_url).unwrap();
 
+    match crypto::init() {
+        Ok(true) => tracing::info!("🔒 Stored invites are encrypted"),
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!("💥 {}", e);
+            tracing::error!("    Please check your `ENCRYPTION_KEY` or `ENCRYPTION_KEY_FILE`");
+            std::process::exit(1);
+        }
+    }
+
+    match mailer::init() {
+        Ok(true) => tracing::info!("📧 Sending emails over SMTP"),
+        Ok(false) => {}
+        Err(e) => {
+            tracing::error!("💥 {}", e);
+            tracing::error!("    Please check your `SMTP_URL` and `SMTP_FROM`");
+            std::process::exit(1);
+        }
+    }
+    if qr::configured_public_url().is_none() {
+        tracing::warn!("⚠️ `PUBLIC_URL` is not set, the invite QR codes and cards are disabled");
+    }
+    if verification::is_enabled() {
+        tracing::info!("📧 Invitees must verify their email");
+    } else if verification::is_requested() {
+        if qr::configured_public_url().is_none() {
+            tracing::error!(
+                "💥 `EMAIL_VERIFICATION` needs `PUBLIC_URL` for the links in the emails"
+            );
+            tracing::error!("    Please set `PUBLIC_URL` to the public address of Librarian");
+            std::process::exit(1);
+        }
+        tracing::warn!("⚠️ `EMAIL_VERIFICATION` needs `SMTP_URL` and `SMTP_FROM`, it's disabled");
+    }
+
     // Test Redis connection
     match redis_client.get_multiplexed_async_connection().await {
-        Ok(_) => {
+        Ok(mut redis_conn) => {
             tracing::info!("  ✨ Connected to Redis");
+
+            for hash in ENCRYPTED_HASHES {
+                if let Err(e) = crypto::check_hash(&mut redis_conn, hash).await {
+                    tracing::error!("  💥 Failed to read the stored records of {}: {}", hash, e);
+                    tracing::error!(
+                        "    Please check your `ENCRYPTION_KEY` and `ENCRYPTION_OLD_KEYS`"
+                    );
+                    std::process::exit(1);
+                }
+            }
+
+            match routes::invite::migrate_plaintext_tokens(&mut redis_conn).await {
+                Ok(0) => {}
+                Ok(migrated) => {
+                    tracing::info!("  🔒 Hashed {} plaintext invite tokens", migrated);
+                }
+                Err(e) => {
+                    tracing::error!("  💥 Failed to hash plaintext invite tokens: {}", e);
+                    std::process::exit(1);
+                }
+            }
+
+            // Re-encrypt the records with the current key in the background
+            tokio::spawn(async move {
+                for hash in ENCRYPTED_HASHES {
+                    match crypto::rotate_hash(&mut redis_conn, hash).await {
+                        Ok(0) => {}
+                        Ok(rotated) => {
+                            tracing::info!(
+                                "🔒 Re-encrypted {} stored records of {}",
+                                rotated,
+                                hash
+                            );
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "💥 Failed to re-encrypt stored records of {}: {}",
+                                hash,
+                                e
+                            )
+                        }
+                    }
+                }
+            });
         }
         Err(e) => {
             tracing::error!("  💥 Failed to connect to Redis: {}", e);
@@ -124,8 +243,21 @@ async fn main() {
         .route("/_/health", get(|| async { "ok" }))
         .nest("/api", routes::api(state.clone()))
         .nest_service("/assets", assets_dir)
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::new().allow_origin(Any))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
+                let client_ip = ClientIp::from_parts(request.extensions(), request.headers());
+
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    version = ?request.version(),
+                    client_ip = client_ip.map(tracing::field::display),
+                )
+            }),
+        )
+        .layer(middleware::from_fn(security::security_headers))
+        .layer(security::cors_layer())
         .with_state(state);
 
     let app = app.fallback(handle_404);
@@ -141,7 +273,12 @@ async fn main() {
         "🚀 Fast serving at: http://{}",
         listener.local_addr().unwrap()
     );
-    axum::serve(listener, app).await.unwrap()
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap()
 }
 
 async fn handle_404(url: Uri) -> Redirect {
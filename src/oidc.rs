@@ -0,0 +1,321 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+use crate::bruteforce::constant_time_eq;
+
+const KLIBRARIAN_OIDC_STATE: &str = "k-librarian:oidc_states";
+/// How long the user can take to login at the provider.
+const OIDC_STATE_TTL: u64 = 60 * 10;
+/// The cookie binding the login to the browser that started it.
+pub const OIDC_BINDING_COOKIE: &str = "k-librarian-oidc";
+const DEFAULT_SCOPES: &str = "openid profile email groups";
+const DEFAULT_ADMIN_CLAIM: &str = "groups";
+
+#[derive(Debug)]
+pub enum OidcError {
+    NotConfigured,
+    InvalidState,
+    Provider(String),
+    InvalidIdToken(&'static str),
+    NotAdmin,
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OidcError::NotConfigured => write!(f, "OIDC is not configured"),
+            OidcError::InvalidState => write!(f, "Invalid or expired login state"),
+            OidcError::Provider(error) => write!(f, "OIDC provider error: {}", error),
+            OidcError::InvalidIdToken(reason) => write!(f, "Invalid ID token: {}", reason),
+            OidcError::NotAdmin => write!(f, "You are not allowed to access the dashboard"),
+            OidcError::Redis(error) => write!(f, "Redis error: {}", error),
+        }
+    }
+}
+
+impl From<redis::RedisError> for OidcError {
+    fn from(error: redis::RedisError) -> Self {
+        OidcError::Redis(error)
+    }
+}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(error: reqwest::Error) -> Self {
+        OidcError::Provider(error.to_string())
+    }
+}
+
+/// The OIDC relying party configuration, from the `OIDC_*` environment variables.
+pub struct OidcConfig {
+    pub name: String,
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    admin_claim: String,
+    admin_value: Option<String>,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Result<Self, OidcError> {
+        let get_env = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let issuer = get_env("OIDC_ISSUER").ok_or(OidcError::NotConfigured)?;
+        let client_id = get_env("OIDC_CLIENT_ID").ok_or(OidcError::NotConfigured)?;
+        let redirect_url = get_env("OIDC_REDIRECT_URL").ok_or(OidcError::NotConfigured)?;
+
+        Ok(OidcConfig {
+            name: get_env("OIDC_NAME").unwrap_or("SSO".to_string()),
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: get_env("OIDC_CLIENT_SECRET"),
+            redirect_url,
+            scopes: get_env("OIDC_SCOPES").unwrap_or(DEFAULT_SCOPES.to_string()),
+            admin_claim: get_env("OIDC_ADMIN_CLAIM").unwrap_or(DEFAULT_ADMIN_CLAIM.to_string()),
+            admin_value: get_env("OIDC_ADMIN_VALUE"),
+        })
+    }
+
+    /// Check if the claims grant admin permission.
+    ///
+    /// The claim can either be a string or an array of strings (e.g. groups), if no
+    /// `OIDC_ADMIN_VALUE` is set a truthy claim is enough.
+    fn is_admin(&self, claims: &serde_json::Value) -> bool {
+        let claim = match claims.get(&self.admin_claim) {
+            Some(claim) => claim,
+            None => return false,
+        };
+
+        match (&self.admin_value, claim) {
+            (Some(value), serde_json::Value::String(claim)) => claim == value,
+            (Some(value), serde_json::Value::Array(claims)) => {
+                claims.iter().any(|claim| claim.as_str() == Some(value))
+            }
+            (None, serde_json::Value::Bool(claim)) => *claim,
+            _ => false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LoginState {
+    nonce: String,
+    code_verifier: String,
+    /// SHA-256 of the binding cookie value.
+    binding_hash: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: Option<String>,
+}
+
+fn random_string() -> String {
+    let mut data = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut data);
+
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+fn hash_binding(binding: &str) -> String {
+    format!("{:x}", Sha256::digest(binding.as_bytes()))
+}
+
+/// The `Set-Cookie` value for the binding, an empty value clears the cookie.
+pub fn binding_cookie(binding: &str) -> String {
+    let max_age = if binding.is_empty() {
+        0
+    } else {
+        OIDC_STATE_TTL
+    };
+
+    format!(
+        "{}={}; Path=/api/auth/oidc; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        OIDC_BINDING_COOKIE, binding, max_age
+    )
+}
+
+async fn discover(
+    client: &reqwest::Client,
+    config: &OidcConfig,
+) -> Result<ProviderMetadata, OidcError> {
+    let metadata: ProviderMetadata = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(OidcError::Provider("issuer mismatch".to_string()));
+    }
+
+    Ok(metadata)
+}
+
+/// Start the authorization code flow, returning the URL to redirect the user to and the
+/// binding to set as the [`binding_cookie`].
+pub async fn start_login(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<(String, String), OidcError> {
+    let config = OidcConfig::from_env()?;
+    let client = reqwest::Client::new();
+    let metadata = discover(&client, &config).await?;
+
+    let state = random_string();
+    let binding = random_string();
+    let login_state = LoginState {
+        nonce: random_string(),
+        code_verifier: random_string(),
+        binding_hash: hash_binding(&binding),
+    };
+    let code_challenge =
+        URL_SAFE_NO_PAD.encode(Sha256::digest(login_state.code_verifier.as_bytes()));
+
+    let _: () = redis_conn
+        .set_ex(
+            format!("{}:{}", KLIBRARIAN_OIDC_STATE, state),
+            serde_json::to_string(&login_state).unwrap(),
+            OIDC_STATE_TTL,
+        )
+        .await?;
+
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|error| OidcError::Provider(error.to_string()))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &login_state.nonce)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    Ok((url.to_string(), binding))
+}
+
+/// Finish the authorization code flow, making sure the user is an admin.
+///
+/// `binding` is the cookie set when the login started, so the callback only works in the
+/// browser that started it.
+pub async fn finish_login(
+    redis_conn: &mut MultiplexedConnection,
+    code: &str,
+    state: &str,
+    binding: Option<&str>,
+) -> Result<serde_json::Value, OidcError> {
+    let config = OidcConfig::from_env()?;
+
+    let key = format!("{}:{}", KLIBRARIAN_OIDC_STATE, state);
+    let data: Option<String> = redis_conn.get_del(&key).await?;
+    let login_state: LoginState = data
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .ok_or(OidcError::InvalidState)?;
+    let bound = binding
+        .is_some_and(|binding| constant_time_eq(&hash_binding(binding), &login_state.binding_hash));
+    if !bound {
+        return Err(OidcError::InvalidState);
+    }
+
+    let client = reqwest::Client::new();
+    let metadata = discover(&client, &config).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", login_state.code_verifier.as_str()),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    }
+
+    let tokens: TokenResponse = client
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // The ID token comes directly from the token endpoint over TLS, so we can rely on
+    // the TLS server validation instead of the token signature (OIDC Core 3.1.3.7)
+    let claims = tokens
+        .id_token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok())
+        .and_then(|payload| serde_json::from_slice::<serde_json::Value>(&payload).ok())
+        .ok_or(OidcError::InvalidIdToken("malformed token"))?;
+
+    if claims.get("iss").and_then(|iss| iss.as_str()) != Some(metadata.issuer.as_str()) {
+        return Err(OidcError::InvalidIdToken("issuer mismatch"));
+    }
+    let audience_ok = match claims.get("aud") {
+        Some(serde_json::Value::String(aud)) => aud == &config.client_id,
+        Some(serde_json::Value::Array(auds)) => auds
+            .iter()
+            .any(|aud| aud.as_str() == Some(config.client_id.as_str())),
+        _ => false,
+    };
+    if !audience_ok {
+        return Err(OidcError::InvalidIdToken("audience mismatch"));
+    }
+    let current_unix = chrono::Utc::now().timestamp();
+    match claims.get("exp").and_then(|exp| exp.as_i64()) {
+        Some(exp) if exp > current_unix => {}
+        _ => return Err(OidcError::InvalidIdToken("token expired")),
+    }
+    if claims.get("nonce").and_then(|nonce| nonce.as_str()) != Some(login_state.nonce.as_str()) {
+        return Err(OidcError::InvalidIdToken("nonce mismatch"));
+    }
+
+    if config.is_admin(&claims) {
+        return Ok(claims);
+    }
+
+    // Some providers only expose the groups from the userinfo endpoint
+    if let (Some(userinfo_endpoint), Some(access_token)) =
+        (&metadata.userinfo_endpoint, &tokens.access_token)
+    {
+        let userinfo: serde_json::Value = client
+            .get(userinfo_endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if userinfo.get("sub") == claims.get("sub") && config.is_admin(&userinfo) {
+            return Ok(claims);
+        }
+    }
+
+    Err(OidcError::NotAdmin)
+}
@@ -0,0 +1,499 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use rand::Rng;
+use sha1::{Digest, Sha1};
+
+use crate::invitecode::WORDLIST;
+
+/// Common passwords and words, ordered by how common they are.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+/// Only the start of longer passwords is scored, anything longer is strong enough anyway.
+const MAX_SCORED_LENGTH: usize = 128;
+/// The number of words in a generated password, each word is 8 bits of entropy.
+const GENERATED_WORDS: usize = 5;
+
+/// The guesses per character that is not part of a pattern, like zxcvbn.
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+/// The keyboard rows, walking along them is as weak as a sequence.
+const KEYBOARD_ROWS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+];
+
+#[derive(Debug)]
+pub enum PasswordError {
+    TooShort(usize),
+    TooLong(usize),
+    TooWeak,
+    Banned,
+    Breached,
+}
+
+impl std::fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordError::TooShort(min) => {
+                write!(f, "Password must be at least {} characters", min)
+            }
+            PasswordError::TooLong(max) => write!(f, "Password must be at most {} characters", max),
+            PasswordError::TooWeak => write!(
+                f,
+                "Password is too easy to guess, try a longer one or a few unrelated words"
+            ),
+            PasswordError::Banned => write!(f, "This password is not allowed"),
+            PasswordError::Breached => write!(
+                f,
+                "This password appeared in a data breach, please choose another one"
+            ),
+        }
+    }
+}
+
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    /// The minimum strength score, from 0 (anything) to 4 (very strong).
+    min_score: u8,
+    banned: HashSet<String>,
+    /// A HIBP-style file of uppercase SHA-1 hashes (`HASH:COUNT`), sorted by hash.
+    breached_file: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    fn from_env() -> Self {
+        let get_env = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let mut banned: HashSet<String> = get_env("PASSWORD_BANNED")
+            .unwrap_or_default()
+            .split(',')
+            .map(|password| password.trim().to_lowercase())
+            .filter(|password| !password.is_empty())
+            .collect();
+        if let Some(path) = get_env("PASSWORD_BANNED_FILE") {
+            match std::fs::read_to_string(&path) {
+                Ok(data) => banned.extend(
+                    data.lines()
+                        .map(|password| password.trim().to_lowercase())
+                        .filter(|password| !password.is_empty()),
+                ),
+                Err(error) => tracing::warn!("Failed to read {}: {}", path, error),
+            }
+        }
+
+        let breached_file = get_env("PASSWORD_BREACHED_FILE").map(PathBuf::from);
+        if let Some(path) = &breached_file {
+            if !path.is_file() {
+                tracing::warn!("`PASSWORD_BREACHED_FILE` not found: {}", path.display());
+            }
+        }
+
+        PasswordPolicy {
+            min_length: get_env("PASSWORD_MIN_LENGTH")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(6),
+            max_length: get_env("PASSWORD_MAX_LENGTH")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(128),
+            min_score: get_env("PASSWORD_MIN_SCORE")
+                .and_then(|value| value.parse::<u8>().ok())
+                .unwrap_or(0)
+                .min(4),
+            banned,
+            breached_file,
+        }
+    }
+
+    pub fn instance() -> &'static PasswordPolicy {
+        static POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+
+        POLICY.get_or_init(PasswordPolicy::from_env)
+    }
+
+    /// Check the password against the policy, returning every violation.
+    ///
+    /// The `user_inputs` (e.g. the email) count as very guessable words.
+    pub async fn check(&self, password: &str, user_inputs: &[&str]) -> Vec<PasswordError> {
+        let mut errors = vec![];
+        let length = password.chars().count();
+
+        // Don't spend any time on huge passwords
+        if length > self.max_length {
+            return vec![PasswordError::TooLong(self.max_length)];
+        }
+        if length < self.min_length {
+            errors.push(PasswordError::TooShort(self.min_length));
+        }
+
+        let lowercase = password.to_lowercase();
+        let is_user_input = user_inputs
+            .iter()
+            .any(|input| !input.is_empty() && input.to_lowercase() == lowercase);
+        if self.banned.contains(&lowercase) || is_user_input {
+            errors.push(PasswordError::Banned);
+        }
+
+        if self.min_score > 0 {
+            let score = score(password, user_inputs);
+            if score < self.min_score {
+                errors.push(PasswordError::TooWeak);
+            }
+        }
+
+        if let Some(path) = self.breached_file.clone() {
+            let password = password.to_string();
+            let breached = tokio::task::spawn_blocking(move || is_breached(&path, &password)).await;
+
+            match breached {
+                Ok(Ok(true)) => errors.push(PasswordError::Breached),
+                Ok(Ok(false)) => {}
+                Ok(Err(error)) => tracing::error!("Failed to check breached passwords: {}", error),
+                Err(error) => tracing::error!("Failed to check breached passwords: {}", error),
+            }
+        }
+
+        errors
+    }
+}
+
+/// Generate a strong password that is still easy to type, e.g. `Amber-River-Lantern-Maple-Otter-42`.
+pub fn generate() -> String {
+    let mut rng = rand::thread_rng();
+
+    let mut parts: Vec<String> = (0..GENERATED_WORDS)
+        .map(|_| {
+            let word = WORDLIST[rng.gen_range(0..WORDLIST.len())];
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    parts.push(rng.gen_range(10..100).to_string());
+
+    parts.join("-")
+}
+
+fn common_passwords() -> &'static Vec<String> {
+    static COMMON: OnceLock<Vec<String>> = OnceLock::new();
+
+    COMMON.get_or_init(|| {
+        COMMON_PASSWORDS
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect()
+    })
+}
+
+/// Undo the common character substitutions, e.g. `p@ssw0rd`.
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        _ => c,
+    }
+}
+
+/// The number of possible characters of the same kind.
+fn char_pool(c: char) -> f64 {
+    if c.is_ascii_lowercase() || c.is_ascii_uppercase() {
+        26.0
+    } else if c.is_ascii_digit() {
+        10.0
+    } else if c.is_ascii() {
+        33.0
+    } else {
+        100.0
+    }
+}
+
+fn keyboard_position(c: char) -> Option<(usize, usize)> {
+    KEYBOARD_ROWS
+        .iter()
+        .enumerate()
+        .find_map(|(row, keys)| keys.find(c).map(|column| (row, column)))
+}
+
+/// The length of the repeated, alphabetical or keyboard sequence starting at `start`.
+fn sequence_length(chars: &[char], start: usize) -> usize {
+    let step = |a: char, b: char| -> Option<i32> {
+        let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+        let delta = b as i32 - a as i32;
+        if delta.abs() <= 1 {
+            return Some(delta);
+        }
+
+        match (keyboard_position(a), keyboard_position(b)) {
+            (Some((row_a, column_a)), Some((row_b, column_b))) if row_a == row_b => {
+                let delta = column_b as i32 - column_a as i32;
+                (delta.abs() == 1).then_some(delta * 100)
+            }
+            _ => None,
+        }
+    };
+
+    if start + 1 >= chars.len() {
+        return 1;
+    }
+    let delta = match step(chars[start], chars[start + 1]) {
+        Some(delta) => delta,
+        None => return 1,
+    };
+
+    let mut length = 2;
+    while start + length < chars.len()
+        && step(chars[start + length - 1], chars[start + length]) == Some(delta)
+    {
+        length += 1;
+    }
+
+    length
+}
+
+/// The rank of the longest common word at `start`, together with its length.
+fn dictionary_match(normalized: &[char], start: usize, words: &[String]) -> Option<(usize, usize)> {
+    words
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| word.chars().count() >= 3)
+        .filter(|(_, word)| {
+            let word: Vec<char> = word.chars().collect();
+            normalized.len() >= start + word.len() && normalized[start..start + word.len()] == word
+        })
+        .map(|(rank, word)| (rank, word.chars().count()))
+        .max_by_key(|(_, length)| *length)
+}
+
+/// Estimate the strength of the password from 0 to 4, in the style of zxcvbn.
+///
+/// The password is split greedily in common words, sequences and random characters, and the
+/// score is derived from the estimated number of guesses to find it.
+pub fn score(password: &str, user_inputs: &[&str]) -> u8 {
+    let chars: Vec<char> = password.chars().take(MAX_SCORED_LENGTH).collect();
+    // One lowercase character each, so both stay aligned
+    let normalized: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .map(unleet)
+        .collect();
+
+    // The user inputs are the first words an attacker would try
+    let mut words: Vec<String> = user_inputs
+        .iter()
+        .flat_map(|input| {
+            input
+                .to_lowercase()
+                .split(['@', '.', '-', '_', '+'])
+                .map(|part| part.to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|part| part.chars().count() >= 3)
+        .collect();
+    words.extend(common_passwords().iter().cloned());
+
+    let mut bits = 0.0;
+    let mut idx = 0;
+    while idx < chars.len() {
+        if let Some((rank, length)) = dictionary_match(&normalized, idx, &words) {
+            // The rank, and a bit for the capitalization or substitutions
+            let modified = chars[idx..idx + length]
+                .iter()
+                .zip(&normalized[idx..idx + length])
+                .any(|(c, normalized)| c != normalized);
+            bits += ((rank + 2) as f64).log2() + if modified { 1.0 } else { 0.0 };
+            idx += length;
+            continue;
+        }
+
+        let length = sequence_length(&chars, idx);
+        if length >= 3 {
+            bits += char_pool(chars[idx]).log2() + (length as f64).log2();
+            idx += length;
+            continue;
+        }
+
+        bits += BRUTEFORCE_CARDINALITY.log2();
+        idx += 1;
+    }
+
+    let guesses_log10 = bits * 2f64.log10();
+    match guesses_log10 {
+        x if x < 3.0 => 0,
+        x if x < 6.0 => 1,
+        x if x < 8.0 => 2,
+        x if x < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Binary search the uppercase SHA-1 of the password in the sorted breached passwords file.
+fn is_breached(path: &Path, password: &str) -> std::io::Result<bool> {
+    let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut low, mut high) = (0u64, reader.get_ref().metadata()?.len());
+
+    let mut line = String::new();
+    while low < high {
+        let mid = low + (high - low) / 2;
+
+        // Move to the start of the next line, unless already at one
+        let mut line_start = mid;
+        if mid > 0 {
+            reader.seek(SeekFrom::Start(mid - 1))?;
+            let mut previous = [0u8; 1];
+            reader.read_exact(&mut previous)?;
+            if previous[0] != b'\n' {
+                line.clear();
+                line_start += reader.read_line(&mut line)? as u64;
+            }
+        } else {
+            reader.seek(SeekFrom::Start(0))?;
+        }
+        if line_start >= high {
+            high = mid;
+            continue;
+        }
+
+        line.clear();
+        let line_length = reader.read_line(&mut line)? as u64;
+        let line_hash = line.split(':').next().unwrap_or_default().trim();
+        match line_hash.to_uppercase().as_str().cmp(hash.as_str()) {
+            std::cmp::Ordering::Equal => return Ok(true),
+            std::cmp::Ordering::Less => low = line_start + line_length,
+            std::cmp::Ordering::Greater => high = mid,
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha1_hex(password: &str) -> String {
+        format!("{:X}", Sha1::digest(password.as_bytes()))
+    }
+
+    /// Write a sorted breached passwords file, with counts of varying length like the HIBP dump.
+    fn breached_file(name: &str, passwords: &[&str]) -> PathBuf {
+        let mut hashes: Vec<String> = passwords
+            .iter()
+            .map(|password| sha1_hex(password))
+            .collect();
+        hashes.sort();
+        let contents: String = hashes
+            .iter()
+            .enumerate()
+            .map(|(idx, hash)| format!("{}:{}\r\n", hash, 10usize.pow(idx as u32 % 7)))
+            .collect();
+
+        let path =
+            std::env::temp_dir().join(format!("k-librarian-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn finds_every_breached_password() {
+        let passwords: Vec<String> = (0..200).map(|idx| format!("password-{}", idx)).collect();
+        let passwords: Vec<&str> = passwords.iter().map(|password| password.as_str()).collect();
+        let path = breached_file("every", &passwords);
+
+        // Including the first and last line of the file, wherever the seeks land
+        for password in &passwords {
+            assert!(is_breached(&path, password).unwrap(), "{}", password);
+        }
+        for idx in 200..400 {
+            assert!(!is_breached(&path, &format!("password-{}", idx)).unwrap());
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn handles_tiny_files() {
+        let path = breached_file("single", &["hunter2"]);
+        assert!(is_breached(&path, "hunter2").unwrap());
+        assert!(!is_breached(&path, "hunter3").unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let path = breached_file("empty", &[]);
+        assert!(!is_breached(&path, "hunter2").unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn handles_lowercase_hashes() {
+        let path =
+            std::env::temp_dir().join(format!("k-librarian-lowercase-{}.txt", std::process::id()));
+        std::fs::write(&path, format!("{}:3\n", sha1_hex("hunter2").to_lowercase())).unwrap();
+
+        assert!(is_breached(&path, "hunter2").unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn scores_weak_passwords_low() {
+        assert_eq!(score("password", &[]), 0);
+        assert_eq!(score("P@ssw0rd", &[]), 0);
+        assert!(score("abcdefghijkl", &[]) <= 1);
+        assert!(score("qwertyuiop", &[]) <= 1);
+        assert!(score("aaaaaaaaaaaa", &[]) <= 1);
+    }
+
+    #[test]
+    fn scores_user_inputs_as_words() {
+        let password = "aliceexample";
+        assert!(score(password, &["alice@example.com"]) < score(password, &[]));
+    }
+
+    #[tokio::test]
+    async fn huge_passwords_are_only_too_long() {
+        let policy = PasswordPolicy {
+            min_length: 6,
+            max_length: 128,
+            min_score: 4,
+            banned: HashSet::new(),
+            breached_file: None,
+        };
+        let password = "a".repeat(5_000_000);
+
+        let errors = policy.check(&password, &[]).await;
+        assert!(matches!(errors[..], [PasswordError::TooLong(128)]));
+
+        // The scorer only looks at the start
+        assert!(score(&password, &[]) <= 1);
+        assert_eq!(score("İİİİ", &[]), score("iiii", &[]));
+    }
+
+    #[test]
+    fn scores_generated_passwords_high() {
+        for _ in 0..20 {
+            assert_eq!(score(&generate(), &[]), 4);
+        }
+        assert_eq!(score("x7#Kp2!vQz9m", &[]), 4);
+    }
+}
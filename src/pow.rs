@@ -0,0 +1,196 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use redis::aio::MultiplexedConnection;
+use sha2::{Digest, Sha256};
+
+const KLIBRARIAN_POW_USED: &str = "k-librarian:pow_used";
+const ALGORITHM: &str = "SHA-256";
+/// How long the client has to solve the challenge and submit the form.
+const CHALLENGE_TTL: u64 = 60 * 10;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+pub enum PowError {
+    Missing,
+    Invalid,
+    Expired,
+    Reused,
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for PowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowError::Missing => write!(f, "Proof-of-work solution is required"),
+            PowError::Invalid => write!(f, "Invalid proof-of-work solution"),
+            PowError::Expired => write!(f, "Proof-of-work challenge expired"),
+            PowError::Reused => write!(f, "Proof-of-work challenge already used"),
+            PowError::Redis(error) => write!(f, "Redis error: {}", error),
+        }
+    }
+}
+
+impl From<redis::RedisError> for PowError {
+    fn from(error: redis::RedisError) -> Self {
+        PowError::Redis(error)
+    }
+}
+
+/// A challenge compatible with ALTCHA, the client need to find the `number` (up to
+/// `maxnumber`) where `SHA-256(salt + number)` equals the `challenge`.
+#[derive(serde::Serialize)]
+pub struct Challenge {
+    algorithm: &'static str,
+    challenge: String,
+    maxnumber: u64,
+    salt: String,
+    signature: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Solution {
+    algorithm: String,
+    challenge: String,
+    number: u64,
+    salt: String,
+    signature: String,
+}
+
+/// The global difficulty (the maximum secret number) from `POW_DIFFICULTY`, `0` disable it.
+pub fn default_difficulty() -> u64 {
+    std::env::var("POW_DIFFICULTY")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+/// The HMAC key used to sign the challenges, `POW_SECRET` or a key derived from the admin `TOKEN`.
+///
+/// The derived key is `HMAC(TOKEN, "k-librarian:pow")`, so the admin token itself is never
+/// used to sign anything handed out to the public.
+fn signing_key() -> Vec<u8> {
+    if let Some(secret) = std::env::var("POW_SECRET")
+        .ok()
+        .filter(|secret| !secret.trim().is_empty())
+    {
+        return secret.into_bytes();
+    }
+
+    let token = std::env::var("TOKEN").unwrap_or_default();
+    let mut mac = HmacSha256::new_from_slice(token.as_bytes()).expect("HMAC accepts any key size");
+    mac.update(b"k-librarian:pow");
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hash_hex(salt: &str, number: u64) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(format!("{}{}", salt, number).as_bytes())
+    )
+}
+
+fn sign(challenge: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&signing_key()).expect("HMAC accepts any key size");
+    mac.update(challenge.as_bytes());
+    mac
+}
+
+/// Create a signed challenge bound to the invite token.
+pub fn create_challenge(invite: &str, difficulty: u64) -> Challenge {
+    let mut data = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut data);
+    let random: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let expires = chrono::Utc::now().timestamp() as u64 + CHALLENGE_TTL;
+    // The parameters are part of the salt, so they are covered by the signature
+    let salt = format!(
+        "{}?expires={}&invite={}",
+        random,
+        expires,
+        urlencoding::encode(invite)
+    );
+
+    let number = rand::random::<u64>() % (difficulty + 1);
+    let challenge = hash_hex(&salt, number);
+    let signature = sign(&challenge)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    Challenge {
+        algorithm: ALGORITHM,
+        challenge,
+        maxnumber: difficulty,
+        salt,
+        signature,
+    }
+}
+
+fn salt_param<'a>(salt: &'a str, key: &str) -> Option<&'a str> {
+    let (_, params) = salt.split_once('?')?;
+
+    params
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+/// Verify the solution for the invite token, each challenge can only be used once.
+pub async fn verify_solution(
+    redis_conn: &mut MultiplexedConnection,
+    invite: &str,
+    solution: Option<&Solution>,
+) -> Result<(), PowError> {
+    let solution = solution.ok_or(PowError::Missing)?;
+
+    if solution.algorithm != ALGORITHM {
+        return Err(PowError::Invalid);
+    }
+
+    let signature = (0..solution.signature.len())
+        .step_by(2)
+        .map(|i| {
+            solution
+                .signature
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(PowError::Invalid)?;
+    sign(&solution.challenge)
+        .verify_slice(&signature)
+        .map_err(|_| PowError::Invalid)?;
+
+    if hash_hex(&solution.salt, solution.number) != solution.challenge {
+        return Err(PowError::Invalid);
+    }
+
+    if salt_param(&solution.salt, "invite") != Some(urlencoding::encode(invite).as_ref()) {
+        return Err(PowError::Invalid);
+    }
+
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+    match salt_param(&solution.salt, "expires").and_then(|exp| exp.parse::<u64>().ok()) {
+        Some(expires) if expires > current_unix => {}
+        _ => return Err(PowError::Expired),
+    }
+
+    let first_use: bool = redis::cmd("SET")
+        .arg(format!("{}:{}", KLIBRARIAN_POW_USED, solution.challenge))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(CHALLENGE_TTL)
+        .query_async::<_, Option<String>>(redis_conn)
+        .await?
+        .is_some();
+    if !first_use {
+        return Err(PowError::Reused);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap, StatusCode},
+};
+use ipnet::IpNet;
+
+const DEFAULT_USER_HEADER: &str = "Remote-User";
+const DEFAULT_GROUPS_HEADER: &str = "Remote-Groups";
+
+/// Parse a comma separated list of CIDRs (or plain IPs) from the environment variable.
+fn parse_cidrs(key: &str) -> Vec<IpNet> {
+    let value = std::env::var(key).unwrap_or_default();
+
+    value
+        .split(',')
+        .map(|cidr| cidr.trim())
+        .filter(|cidr| !cidr.is_empty())
+        .filter_map(|cidr| {
+            let parsed = cidr
+                .parse::<IpNet>()
+                .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from));
+
+            match parsed {
+                Ok(net) => Some(net),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid CIDR in `{}`: {}", key, cidr);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The reverse proxies we trust, from the `TRUSTED_PROXIES` environment variable.
+pub fn trusted_proxies() -> &'static [IpNet] {
+    static TRUSTED_PROXIES: OnceLock<Vec<IpNet>> = OnceLock::new();
+
+    TRUSTED_PROXIES.get_or_init(|| parse_cidrs("TRUSTED_PROXIES"))
+}
+
+/// The networks allowed to access the admin routes, from the `ADMIN_ALLOWLIST` environment variable.
+pub fn admin_allowlist() -> &'static [IpNet] {
+    static ADMIN_ALLOWLIST: OnceLock<Vec<IpNet>> = OnceLock::new();
+
+    ADMIN_ALLOWLIST.get_or_init(|| parse_cidrs("ADMIN_ALLOWLIST"))
+}
+
+/// Check if the client IP is allowed to access the admin routes, everyone is allowed if
+/// the allowlist is empty.
+pub fn is_admin_allowed(ip: IpAddr) -> bool {
+    let allowlist = admin_allowlist();
+    let ip = canonical_ip(ip);
+
+    allowlist.is_empty() || allowlist.iter().any(|net| net.contains(&ip))
+}
+
+/// Convert IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) back into IPv4.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+pub fn is_trusted_proxy(ip: IpAddr) -> bool {
+    let ip = canonical_ip(ip);
+
+    trusted_proxies().iter().any(|net| net.contains(&ip))
+}
+
+/// Parse the node of a `Forwarded` (RFC 7239) `for=` parameter, e.g. `"[2001:db8::1]:4711"`.
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(v6) = node.strip_prefix('[') {
+        return v6.split(']').next()?.parse().ok();
+    }
+
+    // IPv4 with an optional port, obfuscated identifiers and `unknown` are ignored
+    node.split(':').next()?.parse().ok()
+}
+
+/// The header the trusted proxies put the client IP in, from `TRUSTED_PROXY_HEADER`.
+///
+/// Only this header is read, the others might come straight from the client since most proxies
+/// only append to (or overwrite) their own header.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProxyHeader {
+    XForwardedFor,
+    Forwarded,
+    XRealIp,
+}
+
+impl ProxyHeader {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "x-forwarded-for" => Some(ProxyHeader::XForwardedFor),
+            "forwarded" => Some(ProxyHeader::Forwarded),
+            "x-real-ip" => Some(ProxyHeader::XRealIp),
+            _ => None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        static PROXY_HEADER: OnceLock<ProxyHeader> = OnceLock::new();
+
+        *PROXY_HEADER.get_or_init(|| match std::env::var("TRUSTED_PROXY_HEADER") {
+            Ok(value) if !value.trim().is_empty() => {
+                ProxyHeader::parse(&value).unwrap_or_else(|| {
+                    tracing::warn!(
+                        "Ignoring invalid `TRUSTED_PROXY_HEADER`: {}, using `x-forwarded-for`",
+                        value
+                    );
+                    ProxyHeader::XForwardedFor
+                })
+            }
+            _ => ProxyHeader::XForwardedFor,
+        })
+    }
+}
+
+/// Get the forwarded chain (client first) from the proxy header.
+fn forwarded_chain(headers: &HeaderMap, header: ProxyHeader) -> Vec<IpAddr> {
+    let header_values = |name: &str| -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+            .collect()
+    };
+
+    match header {
+        ProxyHeader::Forwarded => header_values("Forwarded")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_forwarded_node(node))
+            })
+            .collect(),
+        ProxyHeader::XForwardedFor => header_values("X-Forwarded-For")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect(),
+        ProxyHeader::XRealIp => header_values("X-Real-IP")
+            .iter()
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect(),
+    }
+}
+
+fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted: &[IpNet],
+    header: ProxyHeader,
+) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let peer = canonical_ip(peer);
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    forwarded_chain(headers, header)
+        .into_iter()
+        .map(canonical_ip)
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .unwrap_or(peer)
+}
+
+/// Resolve the real client IP of the request.
+///
+/// When the direct peer is a trusted proxy, we walk the forwarded chain from the right and
+/// take the first address that is not one of our trusted proxies. The entries left of it were
+/// given by the client and are never used.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    resolve_client_ip(peer, headers, trusted_proxies(), ProxyHeader::from_env())
+}
+
+/// The real client IP of the request, resolved with the trusted proxies headers.
+#[derive(Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    /// Resolve the client IP, `None` if the server was not started with the connection info.
+    pub fn from_parts(extensions: &Extensions, headers: &HeaderMap) -> Option<Self> {
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| ClientIp(client_ip(peer.ip(), headers)))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        ClientIp::from_parts(&parts.extensions, &parts.headers)
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Missing connection info"))
+    }
+}
+
+impl std::fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The forward-auth configuration, used when the reverse proxy already authenticated the admin.
+pub struct ForwardAuthConfig {
+    user_header: String,
+    groups_header: String,
+    admin_group: String,
+}
+
+impl ForwardAuthConfig {
+    /// Get the configuration, `None` if forward-auth is not enabled.
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("FORWARD_AUTH")
+            .map(|value| value.trim().eq_ignore_ascii_case("true") || value.trim() == "1")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let get_env = |key: &str, default: &str| {
+            std::env::var(key)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .unwrap_or(default.to_string())
+        };
+
+        let admin_group = get_env("FORWARD_AUTH_ADMIN_GROUP", "");
+        if admin_group.is_empty() {
+            return None;
+        }
+
+        Some(ForwardAuthConfig {
+            user_header: get_env("FORWARD_AUTH_USER_HEADER", DEFAULT_USER_HEADER),
+            groups_header: get_env("FORWARD_AUTH_GROUPS_HEADER", DEFAULT_GROUPS_HEADER),
+            admin_group,
+        })
+    }
+
+    /// Get the admin username from the proxy headers.
+    ///
+    /// The headers are only trusted if the request comes directly from a trusted proxy,
+    /// and the user is in the admin group.
+    pub fn admin_user(&self, peer: IpAddr, headers: &HeaderMap) -> Option<String> {
+        if !is_trusted_proxy(peer) {
+            return None;
+        }
+
+        let user = headers
+            .get(&self.user_header)
+            .and_then(|user| user.to_str().ok())
+            .map(|user| user.trim())
+            .filter(|user| !user.is_empty())?;
+
+        let is_admin = headers
+            .get_all(&self.groups_header)
+            .iter()
+            .filter_map(|groups| groups.to_str().ok())
+            .flat_map(|groups| groups.split(','))
+            .any(|group| group.trim() == self.admin_group);
+
+        if is_admin {
+            Some(user.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let headers = headers(&[("X-Forwarded-For", "1.1.1.1")]);
+
+        assert_eq!(
+            resolve_client_ip(
+                ip("2.2.2.2"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("2.2.2.2")
+        );
+    }
+
+    #[test]
+    fn spoofed_entries_are_skipped() {
+        // the client sent `X-Forwarded-For: 10.0.0.5, 1.1.1.1`, the proxy appended the real IP
+        let headers = headers(&[("X-Forwarded-For", "10.0.0.5, 1.1.1.1, 3.3.3.3")]);
+
+        assert_eq!(
+            resolve_client_ip(
+                ip("10.0.0.1"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("3.3.3.3")
+        );
+    }
+
+    #[test]
+    fn only_the_configured_header_is_read() {
+        // the proxy only appends `X-Forwarded-For`, `Forwarded` comes from the client
+        let headers = headers(&[
+            ("Forwarded", "for=10.0.0.5"),
+            ("X-Forwarded-For", "3.3.3.3"),
+            ("X-Real-IP", "4.4.4.4"),
+        ]);
+
+        assert_eq!(
+            resolve_client_ip(
+                ip("10.0.0.1"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("3.3.3.3")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &trusted(), ProxyHeader::XRealIp),
+            ip("4.4.4.4")
+        );
+
+        let headers = self::headers(&[
+            (
+                "Forwarded",
+                "for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https",
+            ),
+            ("X-Forwarded-For", "3.3.3.3"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.1"), &headers, &trusted(), ProxyHeader::Forwarded),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn trusted_only_chain_falls_back_to_peer() {
+        let headers = headers(&[("X-Forwarded-For", "10.0.0.5, 10.0.0.6")]);
+
+        assert_eq!(
+            resolve_client_ip(
+                ip("10.0.0.1"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("10.0.0.1")
+        );
+
+        // nothing usable in the header
+        let headers = self::headers(&[("Forwarded", "for=10.0.0.5")]);
+        assert_eq!(
+            resolve_client_ip(
+                ip("10.0.0.1"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn mapped_addresses_are_canonical() {
+        let headers = headers(&[("X-Forwarded-For", "::ffff:3.3.3.3")]);
+
+        assert_eq!(
+            resolve_client_ip(
+                ip("::ffff:10.0.0.1"),
+                &headers,
+                &trusted(),
+                ProxyHeader::XForwardedFor
+            ),
+            ip("3.3.3.3")
+        );
+    }
+
+    #[test]
+    fn proxy_header_is_parsed() {
+        assert_eq!(
+            ProxyHeader::parse("Forwarded"),
+            Some(ProxyHeader::Forwarded)
+        );
+        assert_eq!(
+            ProxyHeader::parse(" x-forwarded-for "),
+            Some(ProxyHeader::XForwardedFor)
+        );
+        assert_eq!(ProxyHeader::parse("x-real-ip"), Some(ProxyHeader::XRealIp));
+        assert_eq!(ProxyHeader::parse("true-client-ip"), None);
+    }
+}
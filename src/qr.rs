@@ -0,0 +1,394 @@
+use qrcode::{Color, EcLevel, QrCode};
+
+/// The quiet zone around the QR code, in modules.
+const QUIET_ZONE: usize = 4;
+/// The size of a module in the PNG, in pixels.
+const PNG_SCALE: usize = 8;
+
+/// A4 in millimeters, the cards are laid out in a 2x4 grid.
+const PAGE_WIDTH: f64 = 210.0;
+const PAGE_HEIGHT: f64 = 297.0;
+const PAGE_MARGIN: f64 = 10.0;
+const CARD_COLUMNS: usize = 2;
+const CARD_ROWS: usize = 4;
+const CARD_GAP: f64 = 6.0;
+/// The PDF user space unit (a point) in millimeters.
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+pub const CARDS_PER_PAGE: usize = CARD_COLUMNS * CARD_ROWS;
+
+#[derive(Debug)]
+pub struct QrError(qrcode::types::QrError);
+
+impl std::fmt::Display for QrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to create QR code: {}", self.0)
+    }
+}
+
+/// The public base URL of Librarian from `PUBLIC_URL`, if configured.
+///
+/// The links that are printed, mailed or shared are only built from it, never from the
+/// request headers.
+pub fn configured_public_url() -> Option<String> {
+    std::env::var("PUBLIC_URL")
+        .ok()
+        .map(|public_url| public_url.trim().trim_end_matches('/').to_string())
+        .filter(|public_url| !public_url.is_empty())
+}
+
+/// The public invite URL for the token.
+pub fn invite_url(base_url: &str, token: &str) -> String {
+    format!("{}/invite?token={}", base_url, urlencoding::encode(token))
+}
+
+/// The QR code modules, `true` for the dark ones, including the quiet zone.
+fn modules(data: &str) -> Result<(usize, Vec<bool>), QrError> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M).map_err(QrError)?;
+    let width = code.width();
+    let colors = code.to_colors();
+
+    let size = width + QUIET_ZONE * 2;
+    let mut modules = vec![false; size * size];
+    for y in 0..width {
+        for x in 0..width {
+            modules[(y + QUIET_ZONE) * size + x + QUIET_ZONE] =
+                colors[y * width + x] == Color::Dark;
+        }
+    }
+
+    Ok((size, modules))
+}
+
+/// The SVG path of the dark modules, one unit per module.
+fn svg_path(size: usize, modules: &[bool]) -> String {
+    let mut path = String::new();
+    for y in 0..size {
+        for x in 0..size {
+            if modules[y * size + x] {
+                path.push_str(&format!("M{} {}h1v1h-1z", x, y));
+            }
+        }
+    }
+
+    path
+}
+
+pub fn render_svg(data: &str) -> Result<String, QrError> {
+    let (size, modules) = modules(data)?;
+
+    Ok(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" width="{px}" height="{px}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#fff"/><path d="{path}" fill="#000"/></svg>"##,
+        size = size,
+        px = size * PNG_SCALE,
+        path = svg_path(size, &modules),
+    ))
+}
+
+pub fn render_png(data: &str) -> Result<Vec<u8>, QrError> {
+    let (size, modules) = modules(data)?;
+    let pixels_size = size * PNG_SCALE;
+
+    let mut pixels = vec![0xFFu8; pixels_size * pixels_size];
+    for (y, row) in pixels.chunks_mut(pixels_size).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if modules[(y / PNG_SCALE) * size + x / PNG_SCALE] {
+                *pixel = 0x00;
+            }
+        }
+    }
+
+    let mut png_data = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, pixels_size as u32, pixels_size as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .expect("writing to a Vec never fails");
+        writer
+            .write_image_data(&pixels)
+            .expect("writing to a Vec never fails");
+    }
+
+    Ok(png_data)
+}
+
+/// A printable invite card.
+pub struct Card {
+    pub code: String,
+    pub url: String,
+    /// The validity of the invite, printed at the bottom of the card.
+    pub validity: Vec<String>,
+}
+
+/// Render the cards on A4 pages of `CARDS_PER_PAGE` as a PDF, ready to print.
+pub fn render_cards(cards: &[Card]) -> Result<Vec<u8>, QrError> {
+    let pages = if cards.is_empty() {
+        vec![render_cards_page(&[])?]
+    } else {
+        cards
+            .chunks(CARDS_PER_PAGE)
+            .map(render_cards_page)
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    Ok(write_pdf(&pages))
+}
+
+/// The content stream of one A4 page of up to `CARDS_PER_PAGE` cards.
+///
+/// The coordinates are in millimeters from the top left corner, like the SVG QR codes.
+fn render_cards_page(cards: &[Card]) -> Result<String, QrError> {
+    let card_width = (PAGE_WIDTH - PAGE_MARGIN * 2.0 - CARD_GAP * (CARD_COLUMNS - 1) as f64)
+        / CARD_COLUMNS as f64;
+    let card_height =
+        (PAGE_HEIGHT - PAGE_MARGIN * 2.0 - CARD_GAP * (CARD_ROWS - 1) as f64) / CARD_ROWS as f64;
+    let qr_size = card_height - 20.0;
+    let text_width = card_width - qr_size - 12.0;
+
+    let mut content = format!(
+        "{:.5} 0 0 {:.5} 0 {:.2} cm\n",
+        POINTS_PER_MM,
+        -POINTS_PER_MM,
+        PAGE_HEIGHT * POINTS_PER_MM
+    );
+    for (idx, card) in cards.iter().enumerate() {
+        let column = idx % CARD_COLUMNS;
+        let row = (idx % CARDS_PER_PAGE) / CARD_COLUMNS;
+
+        let x = PAGE_MARGIN + column as f64 * (card_width + CARD_GAP);
+        let y = PAGE_MARGIN + row as f64 * (card_height + CARD_GAP);
+
+        // The dashed cutting line
+        content.push_str(&format!(
+            "0.6 G 0.3 w [2 1] 0 d {:.2} {:.2} {:.2} {:.2} re S [] 0 d\n",
+            x, y, card_width, card_height
+        ));
+
+        // The dark modules, a rectangle per run on each row
+        let (size, modules) = modules(&card.url)?;
+        let module = qr_size / size as f64;
+        let (qr_x, qr_y) = (x + 3.0, y + 10.0);
+        content.push_str("0 g\n");
+        for my in 0..size {
+            let mut mx = 0;
+            while mx < size {
+                if !modules[my * size + mx] {
+                    mx += 1;
+                    continue;
+                }
+                let run_start = mx;
+                while mx < size && modules[my * size + mx] {
+                    mx += 1;
+                }
+                content.push_str(&format!(
+                    "{:.3} {:.3} {:.3} {:.3} re\n",
+                    qr_x + run_start as f64 * module,
+                    qr_y + my as f64 * module,
+                    (mx - run_start) as f64 * module,
+                    module
+                ));
+            }
+        }
+        content.push_str("f\n");
+
+        let text_x = x + qr_size + 6.0;
+        let code = wrap_code(&card.code);
+        // Shrink the monospace code until it fits the card (a character is 0.6em wide)
+        let code_size = (text_width / (code.chars().count() as f64 * 0.6)).min(3.6);
+        content.push_str(&pdf_text("F2", 5.0, text_x, y + 16.0, "K-Librarian"));
+        content.push_str(&pdf_text("F1", 3.0, text_x, y + 26.0, "Your invite code:"));
+        content.push_str(&pdf_text("F3", code_size, text_x, y + 33.0, &code));
+
+        content.push_str("0.333 g\n");
+        let validity_y = y + card_height - 8.0 - 4.0 * card.validity.len().saturating_sub(1) as f64;
+        for (line, text) in card.validity.iter().enumerate() {
+            content.push_str(&pdf_text(
+                "F1",
+                2.6,
+                text_x,
+                validity_y + line as f64 * 4.0,
+                text,
+            ));
+        }
+    }
+
+    Ok(content)
+}
+
+/// A line of text at the baseline `(x, y)`, flipped back upright in the page coordinates.
+fn pdf_text(font: &str, size: f64, x: f64, y: f64, text: &str) -> String {
+    format!(
+        "BT /{} {:.2} Tf 1 0 0 -1 {:.2} {:.2} Tm ({}) Tj ET\n",
+        font,
+        size,
+        x,
+        y,
+        escape_pdf(text)
+    )
+}
+
+/// Escape the text for a PDF string in `WinAnsiEncoding`, anything else becomes `?`.
+fn escape_pdf(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            '…' => "\\205".to_string(),
+            ' '..='~' => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+/// Write the PDF document, one A4 page per content stream, with the standard fonts.
+fn write_pdf(pages: &[String]) -> Vec<u8> {
+    let fonts = ["Helvetica", "Helvetica-Bold", "Courier-Bold"];
+    // The catalog, the page tree, the fonts, then a page and its content for each page
+    let first_page = 3 + fonts.len();
+    let page_ids: Vec<usize> = (0..pages.len()).map(|idx| first_page + idx * 2).collect();
+
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids
+                .iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+    ];
+    for font in fonts {
+        objects.push(format!(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+            font
+        ));
+    }
+    for (content, id) in pages.iter().zip(&page_ids) {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH * POINTS_PER_MM,
+            PAGE_HEIGHT * POINTS_PER_MM,
+            id + 1
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    for (idx, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n{}\nendobj\n", idx + 1, object).into_bytes());
+    }
+
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+    for offset in offsets {
+        pdf.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .into_bytes(),
+    );
+
+    pdf
+}
+
+/// Long codes (UUIDs, signed invites) don't fit on a card, show only the start of them.
+fn wrap_code(code: &str) -> String {
+    if code.chars().count() > 40 {
+        format!("{}…", code.chars().take(39).collect::<String>())
+    } else {
+        code.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(idx: usize) -> Card {
+        Card {
+            code: format!("code-{}", idx),
+            url: format!("https://librarian.example.com/invite?token=code-{}", idx),
+            validity: vec!["No expiry".to_string()],
+        }
+    }
+
+    fn render(cards: &[Card]) -> String {
+        String::from_utf8(render_cards(cards).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn cards_are_split_in_pages() {
+        let pages = |count: usize| {
+            let cards: Vec<Card> = (0..count).map(card).collect();
+            render(&cards).matches("/Type /Page ").count()
+        };
+
+        assert_eq!(pages(0), 1);
+        assert_eq!(pages(1), 1);
+        assert_eq!(pages(CARDS_PER_PAGE), 1);
+        assert_eq!(pages(CARDS_PER_PAGE + 1), 2);
+        assert_eq!(pages(CARDS_PER_PAGE * 3), 3);
+    }
+
+    #[test]
+    fn pdf_cross_references_are_valid() {
+        let cards: Vec<Card> = (0..CARDS_PER_PAGE + 1).map(card).collect();
+        let pdf = render(&cards);
+
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+
+        // Every object is where the xref table says
+        let xref: usize = pdf
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|tail| tail.lines().next())
+            .and_then(|offset| offset.parse().ok())
+            .unwrap();
+        assert!(pdf[xref..].starts_with("xref\n"));
+        for (idx, entry) in pdf[xref..]
+            .lines()
+            .skip(3)
+            .take_while(|line| line.ends_with(" n "))
+            .enumerate()
+        {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", idx + 1)));
+        }
+
+        // And every stream has the right length
+        for stream in pdf.split("<< /Length ").skip(1) {
+            let (length, rest) = stream.split_once(" >>\nstream\n").unwrap();
+            let length: usize = length.parse().unwrap();
+            assert!(rest[length..].starts_with("endstream"));
+        }
+    }
+
+    #[test]
+    fn card_text_is_escaped() {
+        let mut card = card(0);
+        card.code = "a(b)c\\d é".to_string();
+
+        assert!(render(&[card]).contains("(a\\(b\\)c\\\\d ?) Tj"));
+    }
+
+    #[test]
+    fn long_codes_are_cut() {
+        let mut card = card(0);
+        card.code = "x".repeat(60);
+
+        assert!(render(&[card]).contains(&format!("({}\\205) Tj", "x".repeat(39))));
+    }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use tracing::{error, warn};
+
+use crate::{proxy::ClientIp, AppState};
+
+const KLIBRARIAN_RATE_LIMIT: &str = "k-librarian:rate_limit";
+
+/// A fixed window rate limit for a single route.
+#[derive(Clone)]
+pub struct RateLimit {
+    name: &'static str,
+    /// Maximum amount of requests per window, `0` disable the limit.
+    limit: u64,
+    /// The window size in seconds.
+    window: u64,
+}
+
+impl RateLimit {
+    /// Get the rate limit from the `RATE_LIMIT_<NAME>` environment variable.
+    ///
+    /// The format is `<requests>/<seconds>`, e.g. `30/60` for 30 requests per minute.
+    pub fn from_env(name: &'static str, limit: u64, window: u64) -> Self {
+        let key = format!("RATE_LIMIT_{}", name.to_uppercase());
+
+        let (limit, window) = match std::env::var(&key) {
+            Ok(value) if !value.trim().is_empty() => {
+                let parsed = value.trim().split_once('/').and_then(|(limit, window)| {
+                    let limit = limit.trim().parse::<u64>().ok()?;
+                    let window = window.trim().parse::<u64>().ok().filter(|w| *w > 0)?;
+                    Some((limit, window))
+                });
+
+                match parsed {
+                    Some(parsed) => parsed,
+                    None => {
+                        warn!("Ignoring invalid rate limit in `{}`: {}", key, value);
+                        (limit, window)
+                    }
+                }
+            }
+            _ => (limit, window),
+        };
+
+        RateLimit {
+            name,
+            limit,
+            window,
+        }
+    }
+
+    /// Count the request, returning the seconds until the window reset if over the limit.
+    async fn hit(
+        &self,
+        redis_conn: &mut MultiplexedConnection,
+        ip: IpAddr,
+    ) -> Result<Option<u64>, redis::RedisError> {
+        let current_unix = chrono::Utc::now().timestamp() as u64;
+        let window_start = current_unix - (current_unix % self.window);
+        let key = format!(
+            "{}:{}:{}:{}",
+            KLIBRARIAN_RATE_LIMIT, self.name, ip, window_start
+        );
+
+        let count: u64 = redis_conn.incr(&key, 1).await?;
+        if count == 1 {
+            let _: i32 = redis_conn.expire(&key, self.window as i64).await?;
+        }
+
+        if count > self.limit {
+            Ok(Some((window_start + self.window - current_unix).max(1)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Middleware that rate limit the route by the client IP.
+///
+/// The counters are stored in Redis so the limit is shared between every replica.
+pub async fn rate_limit(
+    State((state, limit)): State<(AppState, RateLimit)>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if limit.limit == 0 {
+        return next.run(request).await;
+    }
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match limit.hit(&mut redis_conn, ip).await {
+        Ok(None) => next.run(request).await,
+        Ok(Some(retry_after)) => {
+            warn!(
+                target: "k_librarian::security",
+                ip = %ip,
+                route = limit.name,
+                "Rate limit exceeded"
+            );
+
+            let mut headers = HeaderMap::new();
+            headers.insert("Retry-After", HeaderValue::from(retry_after));
+
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                headers,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Too many requests, please try again later"
+                })),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            // Fail open, a Redis hiccup should not lock everyone out
+            error!("Failed to check rate limit: {}", err);
+            next.run(request).await
+        }
+    }
+}
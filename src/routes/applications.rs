@@ -0,0 +1,482 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    Json, Router,
+};
+use garde::Validate;
+use tracing::{error, info};
+
+use crate::{
+    application::{self, ApplicationStatus},
+    komga::KomgaClient,
+    password,
+    ratelimit::{rate_limit, RateLimit},
+    AppState,
+};
+
+use super::{
+    invite::{create_application_user, komga_public_host},
+    permission, AuthToken,
+};
+
+#[derive(serde::Serialize, serde::Deserialize, garde::Validate)]
+pub struct ApplicationRejectRequest {
+    /// Shown to the applicant when they check their application.
+    #[garde(length(max = 1000))]
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+async fn get_applications(
+    State(state): State<AppState>,
+    _: AuthToken<permission::ApplicationRead>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match application::get_applications(&mut redis_conn).await {
+        Ok(applications) => {
+            let summaries: Vec<_> = applications
+                .iter()
+                .map(|application| application.summary())
+                .collect();
+
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "ok": true,
+                    "data": summaries,
+                })),
+            )
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get applications: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn approve_application(
+    State(state): State<AppState>,
+    _: AuthToken<permission::ApplicationReview>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut application = match application::get_application(&mut redis_conn, &id).await {
+        Ok(Some(application)) => application,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Application not found"
+                })),
+            )
+        }
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": format!("Failed to get application: {}", error)
+                })),
+            )
+        }
+    };
+    if application.status != ApplicationStatus::Pending {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Application was already decided"
+            })),
+        );
+    }
+
+    // Only one approval at a time, the Komga user must not be created twice
+    if !application::lock_issuing(&mut redis_conn, &id)
+        .await
+        .unwrap_or(false)
+    {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Application is already being approved"
+            })),
+        );
+    }
+
+    // Another approval might have finished between the read and the lock
+    application = match application::get_application(&mut redis_conn, &id).await {
+        Ok(Some(application)) if application.status == ApplicationStatus::Pending => application,
+        _ => {
+            application::unlock_issuing(&mut redis_conn, &id)
+                .await
+                .unwrap_or(());
+            return (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Application was already decided"
+                })),
+            );
+        }
+    };
+
+    // A deferred password is replaced when the applicant get their credentials
+    let password = application
+        .password
+        .clone()
+        .unwrap_or_else(password::generate);
+    let komga = KomgaClient::instance();
+
+    info!("[{}] Approving application...", id);
+    let res = create_application_user(&mut redis_conn, &komga, &application, &password).await;
+    let response = match res {
+        Ok(user_id) => {
+            application.user_id = Some(user_id);
+            application.decide(ApplicationStatus::Approved, None);
+
+            match application::save_application(&mut redis_conn, &application).await {
+                Ok(_) => (
+                    StatusCode::OK,
+                    Json(serde_json::json!({
+                        "ok": true,
+                        "data": application.summary(),
+                    })),
+                ),
+                Err(error) => {
+                    error!("[{}] Failed to save application: {}", id, error);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(serde_json::json!({
+                            "ok": false,
+                            "error": format!("Failed to save application: {}", error)
+                        })),
+                    )
+                }
+            }
+        }
+        Err(error) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to create user: {}", error)
+            })),
+        ),
+    };
+
+    application::unlock_issuing(&mut redis_conn, &id)
+        .await
+        .unwrap_or(());
+
+    response
+}
+
+async fn reject_application(
+    State(state): State<AppState>,
+    _: AuthToken<permission::ApplicationReview>,
+    Path(id): Path<String>,
+    Json(request): Json<ApplicationRejectRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = request.validate(&()) {
+        let mut format_err = String::new();
+        for (field, err) in e.iter() {
+            format_err.push_str(&format!("- {}: {}", field, err));
+            format_err.push('\n');
+        }
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Invalid request:\n{}", format_err)
+            })),
+        );
+    }
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut application = match application::get_application(&mut redis_conn, &id).await {
+        Ok(Some(application)) => application,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Application not found"
+                })),
+            )
+        }
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": format!("Failed to get application: {}", error)
+                })),
+            )
+        }
+    };
+    if application.status != ApplicationStatus::Pending {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Application was already decided"
+            })),
+        );
+    }
+
+    // Don't overwrite an approval in progress
+    if !application::lock_issuing(&mut redis_conn, &id)
+        .await
+        .unwrap_or(false)
+    {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Application is being approved"
+            })),
+        );
+    }
+    application = match application::get_application(&mut redis_conn, &id).await {
+        Ok(Some(application)) if application.status == ApplicationStatus::Pending => application,
+        _ => {
+            application::unlock_issuing(&mut redis_conn, &id)
+                .await
+                .unwrap_or(());
+            return (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Application was already decided"
+                })),
+            );
+        }
+    };
+
+    let reason = request
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    application.decide(ApplicationStatus::Rejected, reason);
+
+    let response = match application::save_application(&mut redis_conn, &application).await {
+        Ok(_) => {
+            info!("[{}] Rejected application", id);
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "ok": true,
+                    "data": application.summary(),
+                })),
+            )
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to save application: {}", error)
+            })),
+        ),
+    };
+
+    application::unlock_issuing(&mut redis_conn, &id)
+        .await
+        .unwrap_or(());
+
+    response
+}
+
+async fn delete_application(
+    State(state): State<AppState>,
+    _: AuthToken<permission::ApplicationReview>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = application::delete_application(&mut redis_conn, &id)
+        .await
+        .unwrap_or(false);
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
+}
+
+/// The applicant checking their application with the ticket they got when applying.
+///
+/// A generated password is only given out once, the first time the approved application is
+/// checked.
+async fn get_application_status(
+    State(state): State<AppState>,
+    Path(ticket): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut application =
+        match application::get_application_by_ticket(&mut redis_conn, &ticket).await {
+            Ok(Some(application)) => application,
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": "Application not found"
+                    })),
+                )
+            }
+            Err(error) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": format!("Failed to get application: {}", error)
+                    })),
+                )
+            }
+        };
+
+    let komga = KomgaClient::instance();
+    let mut credentials: Option<serde_json::Value> = None;
+    let user_id = application.user_id.clone();
+
+    if let (ApplicationStatus::Approved, Some(user_id)) = (application.status, user_id) {
+        if application.password_deferred && !application.credentials_issued {
+            if !application::lock_issuing(&mut redis_conn, &application.id)
+                .await
+                .unwrap_or(false)
+            {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": "Your credentials are being prepared, please try again"
+                    })),
+                );
+            }
+
+            // The credentials might have been given out between the read and the lock
+            application = match application::get_application(&mut redis_conn, &application.id).await
+            {
+                Ok(Some(application)) if !application.credentials_issued => application,
+                _ => {
+                    application::unlock_issuing(&mut redis_conn, &application.id)
+                        .await
+                        .unwrap_or(());
+                    return (
+                        StatusCode::CONFLICT,
+                        Json(serde_json::json!({
+                            "ok": false,
+                            "error": "Your credentials were already given out"
+                        })),
+                    );
+                }
+            };
+
+            let password = password::generate();
+            let res = match komga.update_user_password(&user_id, &password).await {
+                Ok(_) => {
+                    application.credentials_issued = true;
+                    application::save_application(&mut redis_conn, &application)
+                        .await
+                        .map_err(anyhow::Error::from)
+                }
+                Err(error) => Err(error),
+            };
+
+            application::unlock_issuing(&mut redis_conn, &application.id)
+                .await
+                .unwrap_or(());
+
+            match res {
+                Ok(_) => {
+                    credentials = Some(serde_json::json!({
+                        "email": application.email,
+                        "password": password,
+                    }));
+                }
+                Err(error) => {
+                    error!(
+                        "[{}] Failed to issue the credentials: {}",
+                        application.id, error
+                    );
+                    return (
+                        StatusCode::BAD_GATEWAY,
+                        Json(serde_json::json!({
+                            "ok": false,
+                            "error": "Failed to prepare your credentials, please try again later"
+                        })),
+                    );
+                }
+            }
+        }
+    }
+
+    let host =
+        (application.status == ApplicationStatus::Approved).then(|| komga_public_host(&komga));
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "ok": true,
+            "data": {
+                "status": application.status,
+                "reason": application.reason,
+                "createdAt": application.created_at,
+                "decidedAt": application.decided_at,
+                "host": host,
+                "passwordDeferred": application.password_deferred,
+                "credentialsIssued": application.credentials_issued,
+                "credentials": credentials,
+            }
+        })),
+    )
+}
+
+pub fn applications_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", axum::routing::get(get_applications))
+        .route("/:id", axum::routing::delete(delete_application))
+        .route("/:id/approve", axum::routing::post(approve_application))
+        .route("/:id/reject", axum::routing::post(reject_application))
+        .route(
+            "/status/:ticket",
+            axum::routing::get(get_application_status).layer(middleware::from_fn_with_state(
+                (
+                    state.clone(),
+                    RateLimit::from_env("application_status", 30, 60),
+                ),
+                rate_limit,
+            )),
+        )
+        .with_state(state)
+}
@@ -1,63 +1,333 @@
-use axum::{http::StatusCode, response::IntoResponse, Json, Router};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
 
-use crate::AppState;
+use redis::aio::MultiplexedConnection;
 
-use super::AuthToken;
+use crate::{
+    bruteforce,
+    proxy::ClientIp,
+    session::{self, Session},
+    totp, AppState,
+};
+
+use super::{AuthToken, Principal};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct LoginForm {
     token: String,
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LoginTotpForm {
+    challenge: String,
+    code: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LoginSession {
+    token: String,
+    session: Session,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LoginChallenge {
+    #[serde(rename = "mfaRequired")]
+    mfa_required: bool,
+    challenge: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum LoginData {
+    Session(LoginSession),
+    Challenge(LoginChallenge),
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct LoginResponse {
     ok: bool,
     error: Option<String>,
+    data: Option<LoginData>,
 }
 
-async fn auth_login(Json(payload): Json<LoginForm>) -> impl IntoResponse {
-    let token = std::env::var("TOKEN");
+impl LoginResponse {
+    fn error(message: &str) -> Self {
+        LoginResponse {
+            ok: false,
+            error: Some(message.to_string()),
+            data: None,
+        }
+    }
+}
 
-    match token {
-        Ok(token) => {
-            if token == payload.token {
-                (
-                    StatusCode::OK,
-                    Json(LoginResponse {
-                        ok: true,
-                        error: None,
-                    }),
-                )
-            } else {
-                (
+pub(crate) fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.to_string())
+}
+
+/// Create a new admin session and build the login response for it.
+pub(crate) async fn issue_session(
+    redis_conn: &mut MultiplexedConnection,
+    user_agent: Option<String>,
+) -> (StatusCode, Json<LoginResponse>) {
+    match session::create_session(redis_conn, user_agent).await {
+        Ok((token, session)) => (
+            StatusCode::OK,
+            Json(LoginResponse {
+                ok: true,
+                error: None,
+                data: Some(LoginData::Session(LoginSession { token, session })),
+            }),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(LoginResponse::error(&format!(
+                "Failed to create session: {}",
+                error
+            ))),
+        ),
+    }
+}
+
+fn locked_out(retry_after: u64) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("Retry-After", retry_after.into());
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        headers,
+        Json(LoginResponse::error(
+            "Too many failed attempts, please try again later",
+        )),
+    )
+        .into_response()
+}
+
+async fn auth_login(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    Json(payload): Json<LoginForm>,
+) -> Response {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    if let Ok(Some(retry_after)) = bruteforce::check_lockout(&mut redis_conn, client_ip).await {
+        return locked_out(retry_after);
+    }
+
+    let valid = match std::env::var("TOKEN") {
+        Ok(token) => bruteforce::constant_time_eq(&token, &payload.token),
+        Err(_) => false,
+    };
+
+    if !valid {
+        return match bruteforce::record_failure(&mut redis_conn, client_ip, "login").await {
+            Ok(Some(retry_after)) => locked_out(retry_after),
+            _ => (
+                StatusCode::UNAUTHORIZED,
+                Json(LoginResponse::error("Invalid token")),
+            )
+                .into_response(),
+        };
+    }
+
+    let totp_config = match totp::get_config(&mut redis_conn).await {
+        Ok(totp_config) => totp_config,
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(LoginResponse::error(&format!(
+                    "Failed to get TOTP config: {}",
+                    error
+                ))),
+            )
+                .into_response()
+        }
+    };
+
+    if !totp_config.is_enabled() {
+        bruteforce::record_success(&mut redis_conn, client_ip, "login")
+            .await
+            .unwrap_or(());
+        return issue_session(&mut redis_conn, user_agent(&headers))
+            .await
+            .into_response();
+    }
+
+    // Second step is required, the session is only created after the TOTP code
+    match totp::create_login_challenge(&mut redis_conn, user_agent(&headers)).await {
+        Ok(challenge) => (
+            StatusCode::OK,
+            Json(LoginResponse {
+                ok: true,
+                error: None,
+                data: Some(LoginData::Challenge(LoginChallenge {
+                    mfa_required: true,
+                    challenge,
+                })),
+            }),
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(LoginResponse::error(&format!(
+                "Failed to create login challenge: {}",
+                error
+            ))),
+        )
+            .into_response(),
+    }
+}
+
+async fn auth_login_totp(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    Json(payload): Json<LoginTotpForm>,
+) -> Response {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    if let Ok(Some(retry_after)) = bruteforce::check_lockout(&mut redis_conn, client_ip).await {
+        return locked_out(retry_after);
+    }
+
+    match totp::verify_login_challenge(&mut redis_conn, &payload.challenge, &payload.code).await {
+        Ok(Some(challenge)) => {
+            bruteforce::record_success(&mut redis_conn, client_ip, "login_totp")
+                .await
+                .unwrap_or(());
+            issue_session(&mut redis_conn, challenge.user_agent)
+                .await
+                .into_response()
+        }
+        Ok(None) => {
+            match bruteforce::record_failure(&mut redis_conn, client_ip, "login_totp").await {
+                Ok(Some(retry_after)) => locked_out(retry_after),
+                _ => (
                     StatusCode::UNAUTHORIZED,
-                    Json(LoginResponse {
-                        ok: false,
-                        error: Some("Invalid token".to_string()),
-                    }),
+                    Json(LoginResponse::error("Invalid code or expired challenge")),
                 )
+                    .into_response(),
             }
         }
-        Err(_) => (
-            StatusCode::UNAUTHORIZED,
-            Json(LoginResponse {
-                ok: false,
-                error: Some("Invalid token".to_string()),
-            }),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(LoginResponse::error(&format!(
+                "Failed to verify code: {}",
+                error
+            ))),
+        )
+            .into_response(),
+    }
+}
+
+async fn auth_test(auth: AuthToken) -> impl IntoResponse {
+    let (method, user) = match &auth.0 {
+        Principal::Session(_) => ("session", None),
+        Principal::ApiKey(_) => ("apiKey", None),
+        Principal::ForwardAuth(user) => ("forwardAuth", Some(user.clone())),
+    };
+
+    Json(serde_json::json!({
+        "ok": true,
+        "error": null,
+        "data": {
+            "method": method,
+            "user": user,
+        }
+    }))
+}
+
+async fn auth_logout(State(state): State<AppState>, auth: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = match auth.session() {
+        Some(session) => session::revoke_session(&mut redis_conn, &session.id)
+            .await
+            .unwrap_or(false),
+        None => false,
+    };
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
+}
+
+async fn auth_sessions(State(state): State<AppState>, auth: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match session::get_sessions(&mut redis_conn).await {
+        Ok(sessions) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "current": auth.session().map(|session| session.id.clone()),
+                    "sessions": sessions,
+                }
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get sessions: {}", error)
+            })),
         ),
     }
 }
 
-async fn auth_test(AuthToken(_): AuthToken) -> impl IntoResponse {
-    Json(LoginResponse {
-        ok: true,
-        error: None,
-    })
+async fn auth_revoke_session(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = session::revoke_session(&mut redis_conn, &session_id)
+        .await
+        .unwrap_or(false);
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
 }
 
 pub fn auth_routes(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/login", axum::routing::post(auth_login))
+        .route("/login/totp", axum::routing::post(auth_login_totp))
+        .route("/logout", axum::routing::post(auth_logout))
         .route("/test", axum::routing::get(auth_test))
+        .route("/sessions", axum::routing::get(auth_sessions))
+        .route("/sessions/:id", axum::routing::delete(auth_revoke_session))
+        .nest("/oidc", super::oidc::oidc_routes(state.clone()))
+        .nest("/totp", super::totp::totp_routes(state.clone()))
+        .nest("/webauthn", super::webauthn::webauthn_routes(state.clone()))
         .with_state(state)
 }
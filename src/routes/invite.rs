@@ -1,27 +1,41 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
+    middleware,
     response::IntoResponse,
     Json, Router,
 };
 use garde::Validate;
 use redis::{aio::MultiplexedConnection, AsyncCommands};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tracing::{error, info};
 
 use crate::{
+    application::{self, Application},
+    crypto, email,
+    invitecode::{self, TokenFormat},
     komga::{
         KomgaClient, KomgaUserCreate, KomgaUserCreateOption, KomgaUserCreateOptionSharedLibraries,
     },
+    password::{self, PasswordPolicy},
+    pow, qr,
+    ratelimit::{rate_limit, RateLimit},
+    signedlink,
+    verification::{self, VerificationError},
     AppState,
 };
 
-use super::AuthToken;
+use super::{permission, AuthToken};
 
-const KLIBRARIAN_INVITE_TOKEN: &str = "k-librarian:invite_tokens";
+pub(crate) const KLIBRARIAN_INVITE_TOKEN: &str = "k-librarian:invite_tokens";
+/// The Komga users of approved applications that are not fully set up yet.
+pub(crate) const KLIBRARIAN_APPLICATION_USERS: &str = "k-librarian:application_users";
 const DEFAULT_ROLES: &[&str] = &["USER", "FILE_DOWNLOAD", "PAGE_STREAMING"];
+/// The most cards printed at once, each one is looked up and rendered.
+const MAX_INVITE_CARDS: usize = 10 * qr::CARDS_PER_PAGE;
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct InviteOption {
@@ -31,10 +45,119 @@ pub struct InviteOption {
     pub labels_exclude: Option<Vec<String>>,
     #[serde(rename = "sharedLibraries")]
     pub shared_libraries: Option<KomgaUserCreateOptionSharedLibraries>,
-    #[serde(rename = "expiresAt")]
+    #[serde(
+        rename = "expiresAt",
+        default,
+        deserialize_with = "deserialize_timestamp"
+    )]
     pub expire_at: Option<u64>,
+    /// The invite can't be used before this time, e.g. for invites created ahead of an event.
+    #[serde(
+        rename = "notBefore",
+        default,
+        deserialize_with = "deserialize_timestamp"
+    )]
+    pub not_before: Option<u64>,
+    /// Expire the invite this many seconds after it was first viewed.
+    #[serde(rename = "expiresAfterView")]
+    pub expires_after_view: Option<u64>,
     #[serde(rename = "roles")]
     pub roles: Option<Vec<String>>,
+    /// Only allow registering with email addresses from these domains, replacing the global allowlist.
+    #[serde(
+        rename = "emailDomainsAllow",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub email_domains_allow: Option<Vec<String>>,
+    /// Never allow registering with email addresses from these domains.
+    #[serde(
+        rename = "emailDomainsDeny",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub email_domains_deny: Option<Vec<String>>,
+    /// Librarian generate the password, the invitee only give their email (if not bound).
+    #[serde(
+        rename = "generatePassword",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub generate_password: Option<bool>,
+    /// Hold the redemptions until an admin approve them, the invite stays usable for more
+    /// applicants.
+    #[serde(
+        rename = "requiresApproval",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub requires_approval: Option<bool>,
+    /// The questions the applicants answer for the admin reviewing them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub questions: Option<Vec<String>>,
+    /// Only allow registering with this email address.
+    #[serde(
+        rename = "boundEmail",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bound_email: Option<String>,
+    /// Override the global proof-of-work difficulty, `0` disable it for this invite.
+    #[serde(rename = "powDifficulty")]
+    pub pow_difficulty: Option<u64>,
+}
+
+/// Parse a timestamp given as unix seconds or as an ISO-8601 date with a timezone.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|date| date.timestamp().max(0) as u64)
+        .map_err(|_| {
+            format!(
+                "Invalid date `{}`, expected unix seconds or ISO-8601 with a timezone (e.g. 2026-10-19T18:00:00+02:00)",
+                value
+            )
+        })
+}
+
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Unix(u64),
+        Iso(String),
+    }
+
+    match <Option<Timestamp> as serde::Deserialize>::deserialize(deserializer)? {
+        Some(Timestamp::Unix(timestamp)) => Ok(Some(timestamp)),
+        Some(Timestamp::Iso(value)) => parse_timestamp(&value)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl InviteOption {
+    fn pow_difficulty(&self) -> u64 {
+        self.pow_difficulty.unwrap_or_else(pow::default_difficulty)
+    }
+
+    /// Applications generate the password too when the chosen one can't be stored encrypted.
+    pub fn generate_password(&self) -> bool {
+        self.generate_password.unwrap_or(false)
+            || (self.requires_approval() && !crypto::is_enabled())
+    }
+
+    pub fn requires_approval(&self) -> bool {
+        self.requires_approval.unwrap_or(false)
+    }
 }
 
 impl From<InviteOption> for KomgaUserCreateOption {
@@ -49,17 +172,222 @@ impl From<InviteOption> for KomgaUserCreateOption {
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct InviteToken {
-    token: String,
+    /// The SHA-256 hash of the invite token, the token itself is never stored.
+    #[serde(alias = "token")]
+    id: String,
     option: InviteOption,
     user_id: Option<String>,
+    /// The nonce of a stateless signed invite, which is only stored while being applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    /// When the invite was first viewed, the start of the `expiresAfterView` window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    first_viewed_at: Option<u64>,
+}
+
+/// The invite together with the plaintext token, only returned to whoever already know it.
+#[derive(serde::Serialize)]
+pub struct InviteTokenWithToken<'a> {
+    token: &'a str,
+    #[serde(flatten)]
+    invite: &'a InviteToken,
+}
+
+/// Hash the invite token, used as the key of the stored invite.
+pub fn hash_token(token: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(invitecode::canonical(token).as_bytes())
+    )
+}
+
+impl InviteToken {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The invite together with the plaintext token, for whoever just created it.
+    pub fn with_token<'a>(&'a self, token: &'a str) -> InviteTokenWithToken<'a> {
+        InviteTokenWithToken {
+            token,
+            invite: self,
+        }
+    }
+
+    /// Serialize the invite for Redis, encrypted if an encryption key is configured.
+    fn to_stored(&self) -> String {
+        crypto::encrypt(&self.id, &serde_json::to_string(self).unwrap())
+    }
+
+    fn from_stored(id: &str, data: &str) -> Result<Self, anyhow::Error> {
+        let data = crypto::decrypt(id, data).map_err(|error| anyhow::anyhow!("{}", error))?;
+
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// The effective expiry, the earliest of the absolute one and the one since the first view.
+    fn expires_at(&self) -> Option<u64> {
+        let after_view = self
+            .option
+            .expires_after_view
+            .zip(self.first_viewed_at)
+            .map(|(expires_after_view, first_viewed_at)| first_viewed_at + expires_after_view);
+
+        match (self.option.expire_at, after_view) {
+            (Some(expire_at), Some(after_view)) => Some(expire_at.min(after_view)),
+            (expire_at, after_view) => expire_at.or(after_view),
+        }
+    }
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or(timestamp.to_string())
+}
+
+/// Why an invite can't be used right now.
+#[derive(Debug)]
+enum InviteInactive {
+    NotYetActive(u64),
+    Expired,
+}
+
+impl std::fmt::Display for InviteInactive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InviteInactive::NotYetActive(not_before) => write!(
+                f,
+                "Invite token is not active until {}",
+                format_timestamp(*not_before)
+            ),
+            InviteInactive::Expired => write!(f, "Invite token expired"),
+        }
+    }
+}
+
+/// Get the invite by the token hash, `None` if missing or unreadable.
+async fn load_invite(redis_conn: &mut MultiplexedConnection, id: &str) -> Option<InviteToken> {
+    load_invite_from(redis_conn, KLIBRARIAN_INVITE_TOKEN, id).await
+}
+
+async fn load_invite_from(
+    redis_conn: &mut MultiplexedConnection,
+    key: &str,
+    id: &str,
+) -> Option<InviteToken> {
+    let data: Option<String> = redis_conn.hget(key, id).await.unwrap_or(None);
+
+    match InviteToken::from_stored(id, &data?) {
+        Ok(invite) => Some(invite),
+        Err(error) => {
+            error!("[{}] Failed to read invite: {}", id, error);
+            None
+        }
+    }
+}
+
+/// Get the invite for the token, either a stored invite or a valid unused signed invite.
+async fn resolve_invite(
+    redis_conn: &mut MultiplexedConnection,
+    token: &str,
+) -> Option<InviteToken> {
+    let id = hash_token(token);
+
+    // A signed invite that failed halfway is stored to resume it
+    if let Some(invite) = load_invite(redis_conn, &id).await {
+        return Some(invite);
+    }
+    if !signedlink::is_signed(token) {
+        return None;
+    }
+
+    let signed = match signedlink::verify(token) {
+        Ok(signed) => signed,
+        Err(error) => {
+            info!("[{}] Rejected signed invite: {}", id, error);
+            return None;
+        }
+    };
+    match signedlink::is_spent(redis_conn, &signed.nonce).await {
+        Ok(false) => Some(InviteToken {
+            id,
+            option: signed.option,
+            user_id: None,
+            nonce: Some(signed.nonce),
+            first_viewed_at: None,
+        }),
+        _ => None,
+    }
+}
+
+fn is_token_hash(key: &str) -> bool {
+    key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Re-key the invites stored with the plaintext token by older versions.
+pub async fn migrate_plaintext_tokens(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<usize, redis::RedisError> {
+    let all_keys: HashMap<String, String> = redis_conn.hgetall(KLIBRARIAN_INVITE_TOKEN).await?;
+
+    let mut migrated = 0;
+    for (key, value) in all_keys {
+        if is_token_hash(&key) {
+            continue;
+        }
+
+        let mut invite: InviteToken = match serde_json::from_str(&value) {
+            Ok(invite) => invite,
+            Err(_) => continue,
+        };
+        invite.id = hash_token(&key);
+
+        let _: i32 = redis_conn
+            .hset(KLIBRARIAN_INVITE_TOKEN, &invite.id, invite.to_stored())
+            .await?;
+        let _: i32 = redis_conn.hdel(KLIBRARIAN_INVITE_TOKEN, &key).await?;
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateInviteRequest {
+    #[serde(flatten)]
+    option: InviteOption,
+    /// The format of the generated token.
+    #[serde(default)]
+    format: TokenFormat,
+    /// Use this vanity code (e.g. `bookclub-2026`) instead of generating one.
+    code: Option<String>,
+    /// Expire the invite this many seconds after it's created.
+    #[serde(rename = "expiresIn")]
+    expires_in: Option<u64>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, garde::Validate)]
 pub struct InviteTokenApplicationRequest {
+    /// Can be left out for invites bound to an email address.
     #[garde(email)]
-    email: String,
-    #[garde(length(min = 6))]
-    password: String,
+    #[serde(default)]
+    email: Option<String>,
+    /// Checked against the password policy once the invite is known, ignored if the invite
+    /// generate the password.
+    #[garde(skip)]
+    #[serde(default)]
+    password: Option<String>,
+    #[garde(skip)]
+    pow: Option<pow::Solution>,
+    /// The answers to the questions of an invite requiring approval, in order.
+    #[garde(skip)]
+    #[serde(default)]
+    answers: Vec<String>,
+    /// The one-time code sent to the email, when `EMAIL_VERIFICATION` is enabled.
+    #[serde(rename = "verificationCode", default)]
+    #[garde(skip)]
+    verification_code: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -67,39 +395,152 @@ pub struct InviteQuery {
     token: String,
 }
 
+/// Store a new invite with a generated code (or the vanity code), returning it with the token.
+///
+/// `None` if the vanity code is already taken, generated codes are retried with a new one.
+pub async fn store_new_invite(
+    redis_conn: &mut MultiplexedConnection,
+    option: &InviteOption,
+    format: TokenFormat,
+    vanity_code: Option<&str>,
+) -> Result<Option<(String, InviteToken)>, redis::RedisError> {
+    let attempts = if vanity_code.is_some() {
+        1
+    } else {
+        invitecode::MAX_GENERATE_ATTEMPTS
+    };
+
+    for _ in 0..attempts {
+        let token = vanity_code
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| invitecode::generate(format));
+        let invite_token = InviteToken {
+            id: hash_token(&token),
+            user_id: None,
+            option: option.clone(),
+            nonce: None,
+            first_viewed_at: None,
+        };
+
+        // use sets to store our tokens, without overwriting an existing invite
+        let created: bool = redis_conn
+            .hset_nx(
+                KLIBRARIAN_INVITE_TOKEN,
+                invite_token.id.clone(),
+                invite_token.to_stored(),
+            )
+            .await?;
+        if created {
+            return Ok(Some((token, invite_token)));
+        }
+    }
+
+    Ok(None)
+}
+
 pub async fn create_invite_token(
     State(state): State<AppState>,
-    _: AuthToken,
-    Json(option): Json<InviteOption>,
+    _: AuthToken<permission::InviteCreate>,
+    Json(request): Json<CreateInviteRequest>,
 ) -> impl IntoResponse {
-    let token = uuid::Uuid::new_v4().to_string();
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "application/json".parse().unwrap());
+
+    let vanity_code = match request.code.as_deref().map(invitecode::validate_slug) {
+        Some(Ok(code)) => Some(code),
+        Some(Err(error)) => {
+            let wrapped_json: Value = serde_json::json!({
+                "ok": false,
+                "error": error
+            });
 
-    let invite_token = InviteToken {
-        token: token.clone(),
-        user_id: None,
-        option,
+            return (
+                StatusCode::BAD_REQUEST,
+                headers,
+                serde_json::to_string(&wrapped_json).unwrap(),
+            );
+        }
+        None => None,
     };
 
+    let mut option = request.option.clone();
+    if let Some(expires_in) = request.expires_in {
+        let expire_at = chrono::Utc::now().timestamp() as u64 + expires_in;
+        option.expire_at = Some(option.expire_at.map_or(expire_at, |at| at.min(expire_at)));
+    }
+    option.bound_email = option
+        .bound_email
+        .map(|bound_email| email::normalize(&bound_email))
+        .filter(|bound_email| !bound_email.is_empty());
+    let requires_approval = option.requires_approval();
+    option.questions = option
+        .questions
+        .map(|questions| {
+            questions
+                .iter()
+                .map(|question| question.trim().to_string())
+                .filter(|question| !question.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|questions| !questions.is_empty() && requires_approval);
+    if let Some(bound_email) = &option.bound_email {
+        if !email::is_valid(bound_email) {
+            let wrapped_json: Value = serde_json::json!({
+                "ok": false,
+                "error": "Invalid bound email address"
+            });
+
+            return (
+                StatusCode::BAD_REQUEST,
+                headers,
+                serde_json::to_string(&wrapped_json).unwrap(),
+            );
+        }
+    }
+    if let (Some(not_before), Some(expire_at)) = (option.not_before, option.expire_at) {
+        if not_before >= expire_at {
+            let wrapped_json: Value = serde_json::json!({
+                "ok": false,
+                "error": "The invite would expire before it becomes active"
+            });
+
+            return (
+                StatusCode::BAD_REQUEST,
+                headers,
+                serde_json::to_string(&wrapped_json).unwrap(),
+            );
+        }
+    }
+
     let mut redis_conn = state
         .redis
         .get_multiplexed_async_connection()
         .await
         .unwrap();
-    // use sets to store our tokens
-    let res: Result<i32, redis::RedisError> = redis_conn
-        .hset(
-            KLIBRARIAN_INVITE_TOKEN,
-            token.clone(),
-            serde_json::to_string(&invite_token).unwrap(),
-        )
-        .await;
 
-    match res {
-        Ok(_) => {}
-        Err(error) => {
-            let mut headers = HeaderMap::new();
-            headers.insert("Content-Type", "application/json".parse().unwrap());
+    let res = store_new_invite(
+        &mut redis_conn,
+        &option,
+        request.format,
+        vanity_code.as_deref(),
+    )
+    .await;
 
+    let (token, invite_token) = match res {
+        Ok(Some(created)) => created,
+        Ok(None) => {
+            let wrapped_json: Value = serde_json::json!({
+                "ok": false,
+                "error": "The invite code is already taken"
+            });
+
+            return (
+                StatusCode::CONFLICT,
+                headers,
+                serde_json::to_string(&wrapped_json).unwrap(),
+            );
+        }
+        Err(error) => {
             // wrap the json in a {"ok": true, "data": {}} object
             let wrapped_json: Value = serde_json::json!({
                 "ok": false,
@@ -112,13 +553,10 @@ pub async fn create_invite_token(
                 serde_json::to_string(&wrapped_json).unwrap(),
             );
         }
-    }
-
-    let invite_token_json: Value =
-        serde_json::from_str(&serde_json::to_string(&invite_token).unwrap()).unwrap();
+    };
 
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", "application/json".parse().unwrap());
+    // This is the only time the plaintext token is shown
+    let invite_token_json: Value = serde_json::to_value(invite_token.with_token(&token)).unwrap();
 
     // wrap the json in a {"ok": true, "data": {}} object
     let wrapped_json: Value = serde_json::json!({
@@ -133,7 +571,7 @@ pub async fn create_invite_token(
     )
 }
 
-pub async fn get_invite_config(_: AuthToken) -> impl IntoResponse {
+pub async fn get_invite_config(_: AuthToken<permission::ConfigRead>) -> impl IntoResponse {
     // Get all the options available in Komga
 
     let komga = KomgaClient::instance();
@@ -196,25 +634,53 @@ pub async fn get_invite_config(_: AuthToken) -> impl IntoResponse {
     )
 }
 
+/// Check that the invite is usable right now, removing it if it expired.
+///
+/// The first call also starts the `expiresAfterView` window of the invite.
 async fn remove_token_or(
     redis_conn: &mut MultiplexedConnection,
-    token: &InviteToken,
-) -> Result<(), ()> {
+    token: &mut InviteToken,
+) -> Result<(), InviteInactive> {
     let current_unix: u64 = chrono::Utc::now().timestamp() as u64;
 
-    match token.option.expire_at {
-        Some(expire_at) => {
-            if current_unix > expire_at {
-                redis_conn
-                    .hdel(KLIBRARIAN_INVITE_TOKEN, token.token.clone())
+    if let Some(not_before) = token.option.not_before {
+        if current_unix < not_before {
+            return Err(InviteInactive::NotYetActive(not_before));
+        }
+    }
+
+    if token.option.expires_after_view.is_some() && token.first_viewed_at.is_none() {
+        token.first_viewed_at = Some(match &token.nonce {
+            // Signed invites are not stored, only the first view is remembered with the nonce
+            Some(nonce) => signedlink::first_view(redis_conn, nonce, token.option.expire_at)
+                .await
+                .unwrap_or(current_unix),
+            None => {
+                token.first_viewed_at = Some(current_unix);
+                let _: i32 = redis_conn
+                    .hset(KLIBRARIAN_INVITE_TOKEN, token.id.clone(), token.to_stored())
                     .await
                     .unwrap_or(0);
-                Err(())
-            } else {
-                Ok(())
+                current_unix
             }
+        });
+    }
+
+    match token.expires_at() {
+        Some(expire_at) if current_unix > expire_at => {
+            redis_conn
+                .hdel(KLIBRARIAN_INVITE_TOKEN, token.id.clone())
+                .await
+                .unwrap_or(0);
+            // Otherwise the signed invite would be valid again once its record is gone
+            if let Some(nonce) = &token.nonce {
+                signedlink::spend(redis_conn, nonce, token.option.expire_at)
+                    .await
+                    .unwrap_or(());
+            }
+            Err(InviteInactive::Expired)
         }
-        None => Ok(()),
+        _ => Ok(()),
     }
 }
 
@@ -228,23 +694,44 @@ pub async fn get_invite_token(
         .await
         .unwrap();
 
-    let data: Result<String, _> = redis_conn
-        .hget(KLIBRARIAN_INVITE_TOKEN, token.clone())
-        .await;
+    let data = resolve_invite(&mut redis_conn, &token).await;
 
     match data {
-        Ok(data) => {
+        Some(mut raw_val) => {
             let mut headers = HeaderMap::new();
             headers.insert("Content-Type", "application/json".parse().unwrap());
 
-            let raw_val: InviteToken = serde_json::from_str(&data).unwrap();
+            let status = match remove_token_or(&mut redis_conn, &mut raw_val).await {
+                Ok(_) => Ok("active"),
+                // Let the invitee know when to come back
+                Err(InviteInactive::NotYetActive(_)) => Ok("pending"),
+                Err(error) => Err(error),
+            };
+
+            match status {
+                Ok(status) => {
+                    let mut invite_json = serde_json::to_value(InviteTokenWithToken {
+                        token: &token,
+                        invite: &raw_val,
+                    })
+                    .unwrap();
+                    invite_json["status"] = Value::from(status);
+                    invite_json["option"]["generatePassword"] =
+                        Value::from(raw_val.option.generate_password());
+                    // Whoever has the token only get to see a hint of the bound email
+                    if let Some(bound_email) = &raw_val.option.bound_email {
+                        invite_json["option"]
+                            .as_object_mut()
+                            .unwrap()
+                            .remove("boundEmail");
+                        invite_json["boundEmail"] = Value::from(email::mask(bound_email));
+                    }
+                    invite_json["expiresAt"] = Value::from(raw_val.expires_at());
 
-            match remove_token_or(&mut redis_conn, &raw_val).await {
-                Ok(_) => {
                     // wrap the json in a {"ok": true, "data": {}} object
                     let wrapped_json: Value = serde_json::json!({
                         "ok": true,
-                        "data": raw_val,
+                        "data": invite_json,
                     });
 
                     (
@@ -253,11 +740,11 @@ pub async fn get_invite_token(
                         serde_json::to_string(&wrapped_json).unwrap(),
                     )
                 }
-                Err(_) => {
+                Err(error) => {
                     // wrap the json in a {"ok": true, "data": {}} object
                     let wrapped_json: Value = serde_json::json!({
                         "ok": false,
-                        "error": "Invite token expired"
+                        "error": error.to_string()
                     });
 
                     (
@@ -268,7 +755,7 @@ pub async fn get_invite_token(
                 }
             }
         }
-        Err(_) => {
+        None => {
             let mut headers = HeaderMap::new();
             headers.insert("Content-Type", "application/json".parse().unwrap());
 
@@ -287,8 +774,230 @@ pub async fn get_invite_token(
     }
 }
 
+pub async fn get_invite_challenge(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let raw_val = resolve_invite(&mut redis_conn, &token).await;
+
+    match raw_val {
+        Some(mut raw_val) => match remove_token_or(&mut redis_conn, &mut raw_val).await {
+            Ok(_) => {
+                let difficulty = raw_val.option.pow_difficulty();
+                let challenge = (difficulty > 0).then(|| pow::create_challenge(&token, difficulty));
+
+                (
+                    StatusCode::OK,
+                    Json(serde_json::json!({
+                        "ok": true,
+                        "data": challenge,
+                    })),
+                )
+            }
+            Err(error) => (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": error.to_string()
+                })),
+            ),
+        },
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Invite token not found"
+            })),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct InviteQrQuery {
+    format: Option<String>,
+}
+
+pub async fn get_invite_qr(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<InviteQrQuery>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    // Only render the codes of real invites, this is not a QR code generator for any link
+    if resolve_invite(&mut redis_conn, &token).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Invite token not found"
+            })),
+        )
+            .into_response();
+    }
+
+    let url = match qr::configured_public_url() {
+        Some(base_url) => qr::invite_url(&base_url, &token),
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Set `PUBLIC_URL` to create QR codes and cards"
+                })),
+            )
+                .into_response()
+        }
+    };
+
+    let rendered = match query.format.as_deref().unwrap_or("svg") {
+        "svg" => qr::render_svg(&url).map(|svg| ("image/svg+xml", svg.into_bytes())),
+        "png" => qr::render_png(&url).map(|png| ("image/png", png)),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Unknown format, use `svg` or `png`"
+                })),
+            )
+                .into_response()
+        }
+    };
+
+    match rendered {
+        Ok((content_type, data)) => {
+            ([(axum::http::header::CONTENT_TYPE, content_type)], data).into_response()
+        }
+        Err(error) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": error.to_string()
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct InviteCardsRequest {
+    /// At most `MAX_INVITE_CARDS` tokens.
+    tokens: Vec<String>,
+}
+
+/// The validity of the invite for the printed card, one line each.
+fn invite_validity(invite: &InviteToken) -> Vec<String> {
+    let mut validity = vec![];
+    if let Some(not_before) = invite.option.not_before {
+        validity.push(format!("Valid from {}", format_timestamp(not_before)));
+    }
+
+    match (invite.expires_at(), invite.option.expires_after_view) {
+        (Some(expire_at), _) => validity.push(format!("Expires {}", format_timestamp(expire_at))),
+        (None, Some(expires_after_view)) => validity.push(format!(
+            "Expires {}h after first scan",
+            expires_after_view.div_ceil(3600)
+        )),
+        (None, None) => validity.push("No expiry".to_string()),
+    }
+
+    validity
+}
+
+pub async fn create_invite_cards(
+    _: AuthToken<permission::InviteRead>,
+    State(state): State<AppState>,
+    Json(request): Json<InviteCardsRequest>,
+) -> impl IntoResponse {
+    if request.tokens.len() > MAX_INVITE_CARDS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("At most {} cards can be printed at once", MAX_INVITE_CARDS)
+            })),
+        )
+            .into_response();
+    }
+    let base_url = match qr::configured_public_url() {
+        Some(base_url) => base_url,
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Set `PUBLIC_URL` to create QR codes and cards"
+                })),
+            )
+                .into_response()
+        }
+    };
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut cards = vec![];
+    for token in request.tokens {
+        let invite = match resolve_invite(&mut redis_conn, &token).await {
+            Some(invite) => invite,
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": format!("Invite token not found: {}", token)
+                    })),
+                )
+                    .into_response()
+            }
+        };
+
+        cards.push(qr::Card {
+            url: qr::invite_url(&base_url, &token),
+            validity: invite_validity(&invite),
+            code: token,
+        });
+    }
+
+    match qr::render_cards(&cards) {
+        Ok(pdf) => (
+            [
+                (axum::http::header::CONTENT_TYPE, "application/pdf"),
+                (
+                    axum::http::header::CONTENT_DISPOSITION,
+                    "inline; filename=\"invite-cards.pdf\"",
+                ),
+            ],
+            pdf,
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": error.to_string()
+            })),
+        )
+            .into_response(),
+    }
+}
+
 pub async fn delete_invite_token(
-    _: AuthToken,
+    _: AuthToken<permission::InviteDelete>,
     State(state): State<AppState>,
     Path(token): Path<String>,
 ) -> impl IntoResponse {
@@ -299,7 +1008,7 @@ pub async fn delete_invite_token(
         .unwrap();
 
     let data = redis_conn
-        .hdel(KLIBRARIAN_INVITE_TOKEN, token)
+        .hdel(KLIBRARIAN_INVITE_TOKEN, &[hash_token(&token), token])
         .await
         .unwrap_or(0);
 
@@ -320,12 +1029,53 @@ pub async fn delete_invite_token(
     )
 }
 
+/// The Komga URL shown to the new users, `KOMGA_HOSTNAME` if Komga is behind a reverse proxy.
+pub fn komga_public_host(komga: &KomgaClient) -> String {
+    match std::env::var("KOMGA_HOSTNAME") {
+        Ok(komga_hostname) if !komga_hostname.trim().is_empty() => komga_hostname.trim().to_owned(),
+        _ => komga.get_host(),
+    }
+}
+
+/// Create the Komga user of an approved application, resuming a partially created one.
+pub async fn create_application_user(
+    redis_conn: &mut MultiplexedConnection,
+    komga: &KomgaClient,
+    application: &Application,
+    password: &str,
+) -> Result<String, anyhow::Error> {
+    let partial = load_invite_from(redis_conn, KLIBRARIAN_APPLICATION_USERS, &application.id).await;
+    let token = InviteToken {
+        id: application.id.clone(),
+        option: application.option.clone(),
+        user_id: partial.and_then(|partial| partial.user_id),
+        nonce: None,
+        first_viewed_at: None,
+    };
+
+    create_user_in_komga(
+        redis_conn,
+        komga,
+        &token,
+        &application.email,
+        password,
+        KLIBRARIAN_APPLICATION_USERS,
+    )
+    .await
+}
+
+/// Create the Komga user and apply the invite restrictions.
+///
+/// The user ID is kept in the `partial_key` hash until the restrictions are applied, so a failed
+/// attempt is resumed without creating the user twice.
 pub async fn create_user_in_komga(
     redis_conn: &mut MultiplexedConnection,
     komga: &crate::komga::KomgaClient,
     token: &InviteToken,
-    payload: &InviteTokenApplicationRequest,
-) -> Result<(), anyhow::Error> {
+    email: &str,
+    password: &str,
+    partial_key: &str,
+) -> Result<String, anyhow::Error> {
     let roles = token.option.roles.clone().unwrap_or(
         DEFAULT_ROLES
             .to_vec()
@@ -337,69 +1087,71 @@ pub async fn create_user_in_komga(
     if let Some(user_id) = token.user_id.clone() {
         info!(
             "[{} / {}] User already created, applying restriction",
-            token.token, user_id
+            token.id, user_id
         );
+        // The previously generated password was never shown, so replace it
+        if token.option.generate_password() {
+            if let Err(error) = komga.update_user_password(&user_id, password).await {
+                error!("[{}] Failed resetting password... ({})", token.id, error);
+                anyhow::bail!("Failed to reset the generated password")
+            }
+        }
         // do apply user restriction
         let resp_restrict = komga
-            .apply_user_restriction(user_id, token.option.clone().into())
+            .apply_user_restriction(user_id.clone(), token.option.clone().into())
             .await;
 
         match resp_restrict {
             Ok(_) => {
                 // remove the token
                 redis_conn
-                    .hdel(KLIBRARIAN_INVITE_TOKEN, token.token.clone())
+                    .hdel(partial_key, token.id.clone())
                     .await
                     .unwrap_or(0);
 
-                return Ok(());
+                return Ok(user_id);
             }
             Err(error) => {
-                error!(
-                    "[{}] Failed applying restriction... ({})",
-                    token.token, error
-                );
+                error!("[{}] Failed applying restriction... ({})", token.id, error);
                 anyhow::bail!("Failed to apply user restriction")
             }
         }
     }
 
     let user_create = KomgaUserCreate {
-        email: payload.email.clone(),
-        password: payload.password.clone(),
+        email: email.to_string(),
+        password: password.to_string(),
         roles,
     };
 
-    info!("[{}] Creating user...", token.token);
+    info!("[{}] Creating user...", token.id);
     let res = komga.create_user(user_create).await;
 
     match res {
         Ok(data) => {
             // save the user id
             let invite_token = InviteToken {
-                token: token.token.clone(),
+                id: token.id.clone(),
                 option: token.option.clone(),
                 user_id: Some(data.id.clone()),
+                nonce: token.nonce.clone(),
+                first_viewed_at: token.first_viewed_at,
             };
 
             info!(
                 "[{}] Done creating user, saving temp user ID... ({})",
-                token.token,
+                token.id,
                 data.id.clone()
             );
             let _ = redis_conn
-                .hset(
-                    KLIBRARIAN_INVITE_TOKEN,
-                    token.token.clone(),
-                    serde_json::to_string(&invite_token).unwrap(),
-                )
+                .hset(partial_key, token.id.clone(), invite_token.to_stored())
                 .await
                 .unwrap_or(0);
 
             // do user restriction
             info!(
                 "[{}] Applying restriction for... ({})",
-                token.token,
+                token.id,
                 data.id.clone()
             );
             let resp_restrict = komga
@@ -411,20 +1163,20 @@ pub async fn create_user_in_komga(
                     // remove the token
                     info!(
                         "[{}] Done applying restriction, removing token... ({})",
-                        token.token,
+                        token.id,
                         data.id.clone()
                     );
                     redis_conn
-                        .hdel(KLIBRARIAN_INVITE_TOKEN, token.token.clone())
+                        .hdel(partial_key, token.id.clone())
                         .await
                         .unwrap_or(0);
 
-                    Ok(())
+                    Ok(data.id)
                 }
                 Err(_) => {
                     info!(
                         "[{}] Failed applying restriction... ({})",
-                        token.token, data.id
+                        token.id, data.id
                     );
                     anyhow::bail!("Failed to apply user restriction")
                 }
@@ -436,6 +1188,133 @@ pub async fn create_user_in_komga(
     }
 }
 
+/// The maximum length of an answer to the questions of an invite.
+const MAX_ANSWER_LENGTH: usize = 2000;
+
+/// Queue the redemption of an invite requiring approval, the user is created once approved.
+async fn submit_application(
+    redis_conn: &mut MultiplexedConnection,
+    invite: &InviteToken,
+    email: String,
+    password: String,
+    answers: Vec<String>,
+) -> (StatusCode, HeaderMap, String) {
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "application/json".parse().unwrap());
+
+    let questions = invite.option.questions.clone().unwrap_or_default();
+    let answers: Vec<String> = answers
+        .iter()
+        .map(|answer| answer.trim().to_string())
+        .collect();
+    let answer_error = if answers.len() != questions.len() {
+        Some(format!("Expected {} answers", questions.len()))
+    } else if answers.iter().any(|answer| answer.is_empty()) {
+        Some("Every question must be answered".to_string())
+    } else if answers
+        .iter()
+        .any(|answer| answer.chars().count() > MAX_ANSWER_LENGTH)
+    {
+        Some(format!(
+            "Answers must be at most {} characters",
+            MAX_ANSWER_LENGTH
+        ))
+    } else {
+        None
+    };
+    if let Some(error) = answer_error {
+        let wrapped_json: Value = serde_json::json!({
+            "ok": false,
+            "error": format!("Invalid request:\n- answers: {}\n", error),
+            "fields": {
+                "answers": [error]
+            }
+        });
+
+        return (
+            StatusCode::BAD_REQUEST,
+            headers,
+            serde_json::to_string(&wrapped_json).unwrap(),
+        );
+    }
+
+    let applications = match application::get_applications(redis_conn).await {
+        Ok(applications) => applications,
+        Err(error) => {
+            error!("[{}] Failed to get applications: {}", invite.id, error);
+            let wrapped_json: Value = serde_json::json!({
+                "ok": false,
+                "error": "Failed to submit the application"
+            });
+
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                headers,
+                serde_json::to_string(&wrapped_json).unwrap(),
+            );
+        }
+    };
+    let duplicate = applications.iter().any(|application| {
+        application.invite_id == invite.id
+            && application.status == application::ApplicationStatus::Pending
+            && email::matches(&application.email, &email)
+    });
+    if duplicate {
+        let wrapped_json: Value = serde_json::json!({
+            "ok": false,
+            "error": "An application with this email address is already pending"
+        });
+
+        return (
+            StatusCode::CONFLICT,
+            headers,
+            serde_json::to_string(&wrapped_json).unwrap(),
+        );
+    }
+
+    // A generated password is only made once the application is approved
+    let password = (!invite.option.generate_password()).then_some(password);
+    let (application, ticket) = Application::new(
+        invite.id.clone(),
+        invite.option.clone(),
+        email,
+        password,
+        answers,
+    );
+
+    if let Err(error) = application::save_application(redis_conn, &application).await {
+        error!("[{}] Failed to save application: {}", invite.id, error);
+        let wrapped_json: Value = serde_json::json!({
+            "ok": false,
+            "error": "Failed to submit the application"
+        });
+
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            headers,
+            serde_json::to_string(&wrapped_json).unwrap(),
+        );
+    }
+
+    info!(
+        "[{}] Application submitted, waiting for approval ({})",
+        invite.id, application.id
+    );
+    let wrapped_json: Value = serde_json::json!({
+        "ok": true,
+        "data": {
+            "pending": true,
+            "ticket": ticket,
+        }
+    });
+
+    (
+        StatusCode::ACCEPTED,
+        headers,
+        serde_json::to_string(&wrapped_json).unwrap(),
+    )
+}
+
 pub async fn apply_invite_token(
     State(state): State<AppState>,
     Path(token): Path<String>,
@@ -446,15 +1325,21 @@ pub async fn apply_invite_token(
         headers.insert("Content-Type", "application/json".parse().unwrap());
 
         let mut format_err = String::new();
+        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
         for (field, err) in e.iter() {
             format_err.push_str(&format!("- {}: {}", field, err));
             format_err.push('\n');
+            fields
+                .entry(field.to_string())
+                .or_default()
+                .push(err.to_string());
         }
 
         // wrap the json in a {"ok": true, "data": {}} object
         let wrapped_json: Value = serde_json::json!({
             "ok": false,
-            "error": format!("Invalid request:\n{}", format_err)
+            "error": format!("Invalid request:\n{}", format_err),
+            "fields": fields
         });
 
         return (
@@ -469,42 +1354,295 @@ pub async fn apply_invite_token(
         .await
         .unwrap();
 
-    info!("Applying invite token: {}", token);
-    let data: Result<String, _> = redis_conn
-        .hget(KLIBRARIAN_INVITE_TOKEN, token.clone())
-        .await;
+    let token_id = hash_token(&token);
+    info!("Applying invite token: {}", token_id);
+    let data = resolve_invite(&mut redis_conn, &token).await;
 
     match data {
-        Ok(data) => {
+        Some(mut raw_val) => {
             let mut headers = HeaderMap::new();
             headers.insert("Content-Type", "application/json".parse().unwrap());
+            info!("[{}] Found token, checking if expired", token_id);
 
-            let raw_val: InviteToken = serde_json::from_str(&data).unwrap();
-            info!("[{}] Found token, checking if expired", token);
-
-            match remove_token_or(&mut redis_conn, &raw_val).await {
+            match remove_token_or(&mut redis_conn, &mut raw_val).await {
                 Ok(_) => {
-                    info!("[{}] Found active, registering...", token);
+                    // A bound invite only accept its email, the account always gets the bound
+                    // address and not the one as typed
+                    let email = match (&raw_val.option.bound_email, &request.email) {
+                        (Some(bound_email), Some(email)) if !email::matches(bound_email, email) => {
+                            Err((
+                                StatusCode::FORBIDDEN,
+                                "This invite is for a different email address",
+                            ))
+                        }
+                        (Some(bound_email), _) => Ok(email::normalize(bound_email)),
+                        (_, Some(email)) => Ok(email.trim().to_string()),
+                        (None, None) => Err((StatusCode::BAD_REQUEST, "Email is required")),
+                    };
+                    let email = match email {
+                        Ok(email) => email,
+                        Err((status, error)) => {
+                            let wrapped_json: Value = serde_json::json!({
+                                "ok": false,
+                                "error": error
+                            });
+
+                            return (
+                                status,
+                                headers,
+                                serde_json::to_string(&wrapped_json).unwrap(),
+                            );
+                        }
+                    };
+
+                    // The admin already picked the address of a bound invite
+                    if raw_val.option.bound_email.is_none() {
+                        if let Err(error) = email::check_domain(
+                            &email,
+                            raw_val.option.email_domains_allow.as_deref(),
+                            raw_val.option.email_domains_deny.as_deref(),
+                        ) {
+                            info!("[{}] Rejected email: {}", token_id, error);
+                            let wrapped_json: Value = serde_json::json!({
+                                "ok": false,
+                                "error": format!("Invalid request:\n- email: {}\n", error),
+                                "fields": {
+                                    "email": [error.to_string()]
+                                }
+                            });
+
+                            return (
+                                StatusCode::BAD_REQUEST,
+                                headers,
+                                serde_json::to_string(&wrapped_json).unwrap(),
+                            );
+                        }
+                    }
+
+                    // The proof of work comes first, the password policy is the costly part
+                    if raw_val.option.pow_difficulty() > 0 {
+                        if let Err(error) =
+                            pow::verify_solution(&mut redis_conn, &token, request.pow.as_ref())
+                                .await
+                        {
+                            let wrapped_json: Value = serde_json::json!({
+                                "ok": false,
+                                "error": error.to_string()
+                            });
+
+                            return (
+                                StatusCode::FORBIDDEN,
+                                headers,
+                                serde_json::to_string(&wrapped_json).unwrap(),
+                            );
+                        }
+                    }
+
+                    let password = match (raw_val.option.generate_password(), &request.password) {
+                        (true, _) => password::generate(),
+                        (false, Some(password)) => password.clone(),
+                        (false, None) => String::new(),
+                    };
+                    let password_errors = match raw_val.option.generate_password() {
+                        true => vec![],
+                        false => PasswordPolicy::instance().check(&password, &[&email]).await,
+                    };
+                    if !password_errors.is_empty() {
+                        let errors: Vec<String> = password_errors
+                            .iter()
+                            .map(|error| error.to_string())
+                            .collect();
+                        let wrapped_json: Value = serde_json::json!({
+                            "ok": false,
+                            "error": format!(
+                                "Invalid request:\n{}",
+                                errors
+                                    .iter()
+                                    .map(|error| format!("- password: {}\n", error))
+                                    .collect::<String>()
+                            ),
+                            "fields": {
+                                "password": errors
+                            }
+                        });
+
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            headers,
+                            serde_json::to_string(&wrapped_json).unwrap(),
+                        );
+                    }
+
+                    // Hold the registration until the invitee proves they own the address
+                    if verification::is_enabled() {
+                        let verified = match request.verification_code.as_deref() {
+                            Some(code) => {
+                                verification::check_code(&mut redis_conn, &raw_val.id, &email, code)
+                                    .await
+                            }
+                            None => {
+                                // Always set when the verification is enabled
+                                let base_url = qr::configured_public_url().unwrap_or_default();
+                                let sent = verification::send_code(
+                                    &mut redis_conn,
+                                    &raw_val.id,
+                                    &email,
+                                    |code| {
+                                        format!(
+                                            "{}&email={}&code={}",
+                                            qr::invite_url(&base_url, &token),
+                                            urlencoding::encode(&email),
+                                            code
+                                        )
+                                    },
+                                )
+                                .await;
+
+                                match sent {
+                                    Ok(_) => {
+                                        info!("[{}] Sent the verification code", token_id);
+                                        let wrapped_json: Value = serde_json::json!({
+                                            "ok": true,
+                                            "data": {
+                                                "verificationRequired": true,
+                                            }
+                                        });
+
+                                        return (
+                                            StatusCode::ACCEPTED,
+                                            headers,
+                                            serde_json::to_string(&wrapped_json).unwrap(),
+                                        );
+                                    }
+                                    Err(error) => Err(error),
+                                }
+                            }
+                        };
+
+                        match verified {
+                            Ok(_) => {}
+                            Err(error @ VerificationError::TooManySends) => {
+                                let wrapped_json: Value = serde_json::json!({
+                                    "ok": false,
+                                    "error": error.to_string()
+                                });
+
+                                return (
+                                    StatusCode::TOO_MANY_REQUESTS,
+                                    headers,
+                                    serde_json::to_string(&wrapped_json).unwrap(),
+                                );
+                            }
+                            Err(
+                                error @ (VerificationError::Invalid
+                                | VerificationError::TooManyAttempts),
+                            ) => {
+                                let wrapped_json: Value = serde_json::json!({
+                                    "ok": false,
+                                    "error": format!("Invalid request:\n- verificationCode: {}\n", error),
+                                    "fields": {
+                                        "verificationCode": [error.to_string()]
+                                    }
+                                });
+
+                                return (
+                                    StatusCode::BAD_REQUEST,
+                                    headers,
+                                    serde_json::to_string(&wrapped_json).unwrap(),
+                                );
+                            }
+                            Err(error) => {
+                                error!("[{}] Failed to verify the email: {}", token_id, error);
+                                let wrapped_json: Value = serde_json::json!({
+                                    "ok": false,
+                                    "error": "Failed to send the verification email, please try again later"
+                                });
+
+                                return (
+                                    StatusCode::BAD_GATEWAY,
+                                    headers,
+                                    serde_json::to_string(&wrapped_json).unwrap(),
+                                );
+                            }
+                        }
+                    }
+
+                    if raw_val.option.requires_approval() {
+                        return submit_application(
+                            &mut redis_conn,
+                            &raw_val,
+                            email,
+                            password,
+                            request.answers,
+                        )
+                        .await;
+                    }
+
+                    // Signed invites are not stored, so spend the nonce to prevent reuse
+                    let spent_nonce = match (&raw_val.nonce, &raw_val.user_id) {
+                        (Some(nonce), None) => {
+                            if let Err(error) =
+                                signedlink::spend(&mut redis_conn, nonce, raw_val.option.expire_at)
+                                    .await
+                            {
+                                let wrapped_json: Value = serde_json::json!({
+                                    "ok": false,
+                                    "error": error.to_string()
+                                });
+
+                                return (
+                                    StatusCode::FORBIDDEN,
+                                    headers,
+                                    serde_json::to_string(&wrapped_json).unwrap(),
+                                );
+                            }
+                            Some(nonce)
+                        }
+                        _ => None,
+                    };
+
+                    info!("[{}] Found active, registering...", token_id);
                     let komga = KomgaClient::instance();
 
-                    let res =
-                        create_user_in_komga(&mut redis_conn, &komga, &raw_val, &request).await;
+                    let res = create_user_in_komga(
+                        &mut redis_conn,
+                        &komga,
+                        &raw_val,
+                        &email,
+                        &password,
+                        KLIBRARIAN_INVITE_TOKEN,
+                    )
+                    .await;
+
+                    // Give the nonce back if the user was not created at all, so it can be retried
+                    if let (Err(_), Some(nonce)) = (&res, spent_nonce) {
+                        if load_invite(&mut redis_conn, &token_id)
+                            .await
+                            .is_none_or(|invite| invite.user_id.is_none())
+                        {
+                            signedlink::unspend(&mut redis_conn, nonce)
+                                .await
+                                .unwrap_or(());
+                        }
+                    }
 
                     match res {
                         Ok(_) => {
                             // wrap the json in a {"ok": true, "data": {}} object
-                            let mut komga_host = komga.get_host();
-
-                            if let Ok(komga_hostname) = std::env::var("KOMGA_HOSTNAME") {
-                                if !komga_hostname.trim().is_empty() {
-                                    komga_host = komga_hostname.trim().to_owned();
-                                }
-                            }
+                            let komga_host = komga_public_host(&komga);
 
+                            // The generated credentials are only ever shown here
+                            let credentials = raw_val.option.generate_password().then(|| {
+                                serde_json::json!({
+                                    "email": email,
+                                    "password": password,
+                                })
+                            });
                             let wrapped_json: Value = serde_json::json!({
                                 "ok": true,
                                 "data": serde_json::json!({
                                     "host": komga_host,
+                                    "credentials": credentials,
                                 })
                             });
 
@@ -529,11 +1667,11 @@ pub async fn apply_invite_token(
                         }
                     }
                 }
-                Err(_) => {
+                Err(error) => {
                     // wrap the json in a {"ok": true, "data": {}} object
                     let wrapped_json: Value = serde_json::json!({
                         "ok": false,
-                        "error": "Invite token expired"
+                        "error": error.to_string()
                     });
 
                     (
@@ -544,7 +1682,7 @@ pub async fn apply_invite_token(
                 }
             }
         }
-        Err(_) => {
+        None => {
             let mut headers = HeaderMap::new();
             headers.insert("Content-Type", "application/json".parse().unwrap());
 
@@ -564,7 +1702,7 @@ pub async fn apply_invite_token(
 }
 
 pub async fn get_all_invite_token(
-    _: AuthToken,
+    _: AuthToken<permission::InviteRead>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
     let mut redis_conn = state
@@ -579,9 +1717,11 @@ pub async fn get_all_invite_token(
         .unwrap_or(HashMap::new());
 
     let mut merged_token = vec![];
-    for (_, value) in all_keys {
-        let raw_val: InviteToken = serde_json::from_str(&value).unwrap();
-        merged_token.push(raw_val);
+    for (id, value) in all_keys {
+        match InviteToken::from_stored(&id, &value) {
+            Ok(raw_val) => merged_token.push(raw_val),
+            Err(error) => error!("[{}] Failed to read invite: {}", id, error),
+        }
     }
 
     let mut headers = HeaderMap::new();
@@ -608,9 +1748,35 @@ pub fn invite_routes(state: AppState) -> Router<AppState> {
         )
         .route(
             "/:token",
-            axum::routing::get(get_invite_token).delete(delete_invite_token),
+            axum::routing::get(get_invite_token)
+                .layer(middleware::from_fn_with_state(
+                    (state.clone(), RateLimit::from_env("invite_get", 30, 60)),
+                    rate_limit,
+                ))
+                .delete(delete_invite_token),
+        )
+        .route(
+            "/:token/challenge",
+            axum::routing::get(get_invite_challenge).layer(middleware::from_fn_with_state(
+                (state.clone(), RateLimit::from_env("invite_get", 30, 60)),
+                rate_limit,
+            )),
+        )
+        .route(
+            "/:token/apply",
+            axum::routing::post(apply_invite_token).layer(middleware::from_fn_with_state(
+                (state.clone(), RateLimit::from_env("invite_apply", 5, 60)),
+                rate_limit,
+            )),
+        )
+        .route(
+            "/:token/qr",
+            axum::routing::get(get_invite_qr).layer(middleware::from_fn_with_state(
+                (state.clone(), RateLimit::from_env("invite_get", 30, 60)),
+                rate_limit,
+            )),
         )
-        .route("/:token/apply", axum::routing::post(apply_invite_token))
+        .route("/cards", axum::routing::post(create_invite_cards))
         .route("/config", axum::routing::get(get_invite_config))
         .with_state(state)
 }
@@ -0,0 +1,132 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json, Router,
+};
+use garde::Validate;
+
+use crate::{
+    apikey::{self, Scope},
+    AppState,
+};
+
+use super::AuthToken;
+
+#[derive(serde::Serialize, serde::Deserialize, garde::Validate)]
+pub struct ApiKeyCreateRequest {
+    #[garde(length(min = 1, max = 100))]
+    name: String,
+    #[garde(length(min = 1))]
+    scopes: Vec<Scope>,
+    #[serde(rename = "expiresAt")]
+    #[garde(skip)]
+    expire_at: Option<u64>,
+}
+
+async fn create_api_key(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Json(request): Json<ApiKeyCreateRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = request.validate(&()) {
+        let mut format_err = String::new();
+        for (field, err) in e.iter() {
+            format_err.push_str(&format!("- {}: {}", field, err));
+            format_err.push('\n');
+        }
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Invalid request:\n{}", format_err)
+            })),
+        );
+    }
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut scopes: Vec<Scope> = vec![];
+    for scope in request.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    match apikey::create_api_key(&mut redis_conn, request.name, scopes, request.expire_at).await {
+        Ok((key, api_key)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "key": key,
+                    "apiKey": api_key,
+                }
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to create API key: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn get_api_keys(State(state): State<AppState>, _: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match apikey::get_api_keys(&mut redis_conn).await {
+        Ok(api_keys) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": api_keys,
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get API keys: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Path(key_id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = apikey::revoke_api_key(&mut redis_conn, &key_id)
+        .await
+        .unwrap_or(false);
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
+}
+
+pub fn keys_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", axum::routing::get(get_api_keys).post(create_api_key))
+        .route("/:id", axum::routing::delete(revoke_api_key))
+        .with_state(state)
+}
@@ -1,75 +1,286 @@
+use std::{marker::PhantomData, net::SocketAddr};
+
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    extract::{ConnectInfo, FromRef, FromRequestParts, Request},
+    http::{request::Parts, Extensions, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     Router,
 };
 
-use crate::AppState;
+use crate::{
+    apikey::{self, ApiKey, Scope, API_KEY_PREFIX},
+    bruteforce,
+    proxy::{self, ClientIp, ForwardAuthConfig},
+    session::{self, Session},
+    AppState,
+};
 
+pub mod applications;
 pub mod auth;
 pub mod invite;
+pub mod keys;
+pub mod oidc;
+pub mod requests;
+pub mod totp;
+pub mod webauthn;
 
 pub fn api(state: AppState) -> Router<AppState> {
     Router::new()
-        .nest("/auth", auth::auth_routes(state.clone()))
+        .nest(
+            "/applications",
+            applications::applications_routes(state.clone()),
+        )
+        .nest(
+            "/auth",
+            auth::auth_routes(state.clone()).layer(middleware::from_fn(admin_allowlist)),
+        )
         .nest("/invite", invite::invite_routes(state.clone()))
+        .nest(
+            "/keys",
+            keys::keys_routes(state.clone()).layer(middleware::from_fn(admin_allowlist)),
+        )
+        .nest("/requests", requests::requests_routes(state.clone()))
         .with_state(state.clone())
 }
 
-#[allow(dead_code)]
-pub struct AuthToken(HeaderValue);
+/// Check the client IP against `ADMIN_ALLOWLIST`.
+fn is_admin_allowed(extensions: &Extensions, headers: &HeaderMap) -> bool {
+    match ClientIp::from_parts(extensions, headers) {
+        Some(ClientIp(client_ip)) => proxy::is_admin_allowed(client_ip),
+        None => proxy::admin_allowlist().is_empty(),
+    }
+}
+
+/// Middleware that only lets the `ADMIN_ALLOWLIST` through, for the routers that are admin only.
+///
+/// The routers mixing public and admin routes rely on the check in [`AuthToken`] instead.
+pub async fn admin_allowlist(request: Request, next: Next) -> Response {
+    if !is_admin_allowed(request.extensions(), request.headers()) {
+        return RejectAuthToken::forbidden("Your IP address is not allowed to access this")
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Who is making an authenticated request.
+pub enum Principal {
+    /// An admin logged in to the dashboard, allowed to do everything.
+    Session(Session),
+    /// An API key, only allowed to do what the scopes permit.
+    ApiKey(ApiKey),
+    /// An admin authenticated by the trusted reverse proxy (forward-auth), with the username.
+    ForwardAuth(String),
+}
+
+impl Principal {
+    fn is_permitted(&self, scopes: Option<&[Scope]>) -> bool {
+        match (self, scopes) {
+            (Principal::Session(_), _) | (Principal::ForwardAuth(_), _) => true,
+            (Principal::ApiKey(api_key), Some(scopes)) => {
+                scopes.iter().all(|scope| api_key.has_scope(*scope))
+            }
+            (Principal::ApiKey(_), None) => false,
+        }
+    }
+}
+
+/// The permission a route requires from the [`AuthToken`] extractor.
+pub trait Permission {
+    /// The scopes an API key needs, `None` means only an admin session is permitted.
+    const SCOPES: Option<&'static [Scope]>;
+}
+
+pub mod permission {
+    use super::{Permission, Scope};
+
+    macro_rules! permission {
+        ($name:ident) => {
+            pub struct $name;
+
+            impl Permission for $name {
+                const SCOPES: Option<&'static [Scope]> = None;
+            }
+        };
+        ($name:ident, $($scope:expr),+) => {
+            pub struct $name;
+
+            impl Permission for $name {
+                const SCOPES: Option<&'static [Scope]> = Some(&[$($scope),+]);
+            }
+        };
+    }
+
+    permission!(Admin);
+    permission!(InviteCreate, Scope::InviteCreate);
+    permission!(InviteRead, Scope::InviteRead);
+    permission!(InviteDelete, Scope::InviteDelete);
+    permission!(ConfigRead, Scope::ConfigRead);
+    permission!(ApplicationRead, Scope::ApplicationRead);
+    permission!(ApplicationReview, Scope::ApplicationReview);
+    permission!(RequestRead, Scope::RequestRead);
+    permission!(RequestReview, Scope::RequestReview);
+    // Inviting a request creates an invite that can grant any roles
+    permission!(RequestInvite, Scope::RequestReview, Scope::InviteCreate);
+}
+
+/// An authenticated request, extracted from the `Authorization: Bearer <token>` header.
+///
+/// The token is either an admin session or an API key, API keys are rejected
+/// when they are missing the scope required by `P`.
+pub struct AuthToken<P: Permission = permission::Admin>(pub Principal, PhantomData<P>);
+
+impl<P: Permission> AuthToken<P> {
+    /// The admin session, if the request is not made with an API key.
+    pub fn session(&self) -> Option<&Session> {
+        match &self.0 {
+            Principal::Session(session) => Some(session),
+            Principal::ApiKey(_) | Principal::ForwardAuth(_) => None,
+        }
+    }
+}
 
 #[derive(serde::Serialize)]
 pub struct RejectAuthToken {
     ok: bool,
     error: &'static str,
+    #[serde(skip)]
+    status: StatusCode,
+    #[serde(skip)]
+    retry_after: Option<u64>,
+}
+
+impl RejectAuthToken {
+    fn unauthorized(error: &'static str) -> Self {
+        RejectAuthToken {
+            ok: false,
+            error,
+            status: StatusCode::UNAUTHORIZED,
+            retry_after: None,
+        }
+    }
+
+    fn forbidden(error: &'static str) -> Self {
+        RejectAuthToken {
+            ok: false,
+            error,
+            status: StatusCode::FORBIDDEN,
+            retry_after: None,
+        }
+    }
+
+    fn locked_out(retry_after: u64) -> Self {
+        RejectAuthToken {
+            ok: false,
+            error: "Too many failed attempts, please try again later",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(retry_after),
+        }
+    }
 }
 
 #[async_trait]
-impl<S> FromRequestParts<S> for AuthToken
+impl<S, P> FromRequestParts<S> for AuthToken<P>
 where
+    AppState: FromRef<S>,
     S: Send + Sync,
+    P: Permission,
 {
     type Rejection = RejectAuthToken;
 
-    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        if let Some(auth_header) = parts.headers.get("Authorization") {
-            let auth_str = auth_header.to_str().unwrap();
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| peer.ip());
+        let client_ip =
+            ClientIp::from_parts(&parts.extensions, &parts.headers).map(|ClientIp(ip)| ip);
 
-            if auth_str.starts_with("Bearer ") {
-                let token = auth_str.replace("Bearer ", "");
-                // Verify with env
+        if !is_admin_allowed(&parts.extensions, &parts.headers) {
+            return Err(RejectAuthToken::forbidden(
+                "Your IP address is not allowed to access this",
+            ));
+        }
 
-                if token != std::env::var("TOKEN").unwrap() {
-                    return Err(RejectAuthToken {
-                        ok: false,
-                        error: "Invalid token",
-                    });
-                }
+        // The reverse proxy might already authenticated the admin for us
+        if let (Some(forward_auth), Some(peer)) = (ForwardAuthConfig::from_env(), peer) {
+            if let Some(user) = forward_auth.admin_user(peer, &parts.headers) {
+                return Ok(AuthToken(Principal::ForwardAuth(user), PhantomData));
+            }
+        }
+
+        let auth_header = match parts.headers.get("Authorization") {
+            Some(auth_header) => auth_header,
+            None => {
+                return Err(RejectAuthToken::unauthorized(
+                    "Missing Authorization header",
+                ))
+            }
+        };
+
+        let token = match auth_header
+            .to_str()
+            .ok()
+            .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim(),
+            None => return Err(RejectAuthToken::unauthorized("Missing Bearer prefix")),
+        };
+
+        let state = AppState::from_ref(state);
+        let mut redis_conn = match state.redis.get_multiplexed_async_connection().await {
+            Ok(redis_conn) => redis_conn,
+            Err(_) => return Err(RejectAuthToken::unauthorized("Failed to verify token")),
+        };
+
+        if let Some(client_ip) = client_ip {
+            if let Ok(Some(retry_after)) =
+                bruteforce::check_lockout(&mut redis_conn, client_ip).await
+            {
+                return Err(RejectAuthToken::locked_out(retry_after));
+            }
+        }
 
-                match HeaderValue::from_str(&token) {
-                    Ok(token) => Ok(AuthToken(token)),
-                    Err(_) => {
-                        return Err(RejectAuthToken {
-                            ok: false,
-                            error: "Invalid token format",
-                        });
+        let principal = if token.starts_with(API_KEY_PREFIX) {
+            match apikey::verify_api_key(&mut redis_conn, token).await {
+                Ok(api_key) => api_key.map(Principal::ApiKey),
+                Err(_) => return Err(RejectAuthToken::unauthorized("Failed to verify API key")),
+            }
+        } else {
+            match session::verify_session(&mut redis_conn, token).await {
+                Ok(session) => session.map(Principal::Session),
+                Err(_) => return Err(RejectAuthToken::unauthorized("Failed to verify session")),
+            }
+        };
+
+        let principal = match principal {
+            Some(principal) => principal,
+            None => {
+                // An unknown session is usually an expired one the dashboard is still sending,
+                // only the API keys are counted and only against the IP
+                if let (Some(client_ip), true) = (client_ip, token.starts_with(API_KEY_PREFIX)) {
+                    if let Ok(Some(retry_after)) =
+                        bruteforce::record_ip_failure(&mut redis_conn, client_ip, "api_key").await
+                    {
+                        return Err(RejectAuthToken::locked_out(retry_after));
                     }
                 }
-            } else {
-                Err(RejectAuthToken {
-                    ok: false,
-                    error: "Missing Bearer prefix",
-                })
+
+                return Err(RejectAuthToken::unauthorized(
+                    "Invalid or expired session or API key",
+                ));
             }
-        } else {
-            Err(RejectAuthToken {
-                ok: false,
-                error: "Missing Authorization header",
-            })
+        };
+
+        if !principal.is_permitted(P::SCOPES) {
+            return Err(RejectAuthToken::forbidden(
+                "API key is missing the required scope",
+            ));
         }
+
+        Ok(AuthToken(principal, PhantomData))
     }
 }
 
@@ -79,7 +290,10 @@ impl IntoResponse for RejectAuthToken {
         let response = serde_json::to_string(&self).unwrap();
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert("Retry-After", retry_after.into());
+        }
 
-        (StatusCode::UNAUTHORIZED, headers, response).into_response()
+        (self.status, headers, response).into_response()
     }
 }
@@ -0,0 +1,171 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    Json, Router,
+};
+use tracing::{error, info};
+use urlencoding::encode;
+
+use crate::{
+    oidc::{self, OidcConfig, OidcError},
+    session, AppState,
+};
+
+use super::auth::user_agent;
+
+#[derive(serde::Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// The binding cookie sent back to the callback.
+fn binding_from_cookies(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == oidc::OIDC_BINDING_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Redirect back to the dashboard, the result is passed in the URL fragment so it never
+/// reaches any server logs.
+fn redirect_dashboard(fragment: String) -> Redirect {
+    Redirect::to(&format!("/?redirect={}#{}", encode("/admin"), fragment))
+}
+
+async fn oidc_config() -> impl IntoResponse {
+    let config = OidcConfig::from_env().ok();
+
+    Json(serde_json::json!({
+        "ok": true,
+        "data": {
+            "enabled": config.is_some(),
+            "name": config.map(|config| config.name),
+        }
+    }))
+}
+
+async fn oidc_login(State(state): State<AppState>) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match oidc::start_login(&mut redis_conn).await {
+        Ok((url, binding)) => (
+            [(header::SET_COOKIE, oidc::binding_cookie(&binding))],
+            Redirect::to(&url),
+        )
+            .into_response(),
+        Err(OidcError::NotConfigured) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": OidcError::NotConfigured.to_string()
+            })),
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Failed to start OIDC login: {}", err);
+            redirect_dashboard(format!("error={}", encode(&err.to_string()))).into_response()
+        }
+    }
+}
+
+async fn oidc_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+) -> impl IntoResponse {
+    // The binding is single use, whatever the outcome
+    let clear_cookie = [(header::SET_COOKIE, oidc::binding_cookie(""))];
+
+    (clear_cookie, finish_callback(state, headers, query).await)
+}
+
+async fn finish_callback(state: AppState, headers: HeaderMap, query: CallbackQuery) -> Redirect {
+    if let Some(err) = query.error {
+        let message = query.error_description.unwrap_or(err);
+        return redirect_dashboard(format!("error={}", encode(&message)));
+    }
+
+    let (code, login_state) = match (query.code, query.state) {
+        (Some(code), Some(login_state)) => (code, login_state),
+        _ => {
+            return redirect_dashboard(format!(
+                "error={}",
+                encode(&OidcError::InvalidState.to_string())
+            ))
+        }
+    };
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let binding = binding_from_cookies(&headers);
+    let claims = match oidc::finish_login(&mut redis_conn, &code, &login_state, binding).await {
+        Ok(claims) => claims,
+        Err(err) => {
+            error!("Failed to finish OIDC login: {}", err);
+            return redirect_dashboard(format!("error={}", encode(&err.to_string())));
+        }
+    };
+
+    info!(
+        "OIDC login for: {}",
+        claims
+            .get("preferred_username")
+            .or(claims.get("sub"))
+            .and_then(|sub| sub.as_str())
+            .unwrap_or("unknown")
+    );
+
+    match session::create_session(&mut redis_conn, user_agent(&headers)).await {
+        Ok((token, _)) => redirect_dashboard(format!("token={}", token)),
+        Err(err) => redirect_dashboard(format!(
+            "error={}",
+            encode(&format!("Failed to create session: {}", err))
+        )),
+    }
+}
+
+pub fn oidc_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", axum::routing::get(oidc_config))
+        .route("/login", axum::routing::get(oidc_login))
+        .route("/callback", axum::routing::get(oidc_callback))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_binding_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(binding_from_cookies(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            "theme=dark; k-librarian-oidc=abc=; other=1"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(binding_from_cookies(&headers), Some("abc="));
+
+        headers.insert(header::COOKIE, "k-librarian-oidc-old=abc".parse().unwrap());
+        assert_eq!(binding_from_cookies(&headers), None);
+    }
+}
@@ -0,0 +1,570 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    Json, Router,
+};
+use garde::Validate;
+use redis::aio::MultiplexedConnection;
+use tracing::{error, info};
+
+use crate::{
+    accessrequest::{self, AccessRequest, AccessRequestStatus},
+    email,
+    invitecode::TokenFormat,
+    komga::{KomgaClient, KomgaUserCreateOptionSharedLibraries},
+    qr,
+    ratelimit::{rate_limit, RateLimit},
+    AppState,
+};
+
+use super::{
+    invite::{store_new_invite, InviteOption},
+    permission, AuthToken,
+};
+
+/// How long the invites created from a request are valid by default, a week.
+const DEFAULT_INVITE_EXPIRY: u64 = 7 * 24 * 60 * 60;
+
+#[derive(serde::Serialize, serde::Deserialize, garde::Validate)]
+pub struct AccessRequestCreateRequest {
+    #[garde(email)]
+    email: String,
+    #[garde(length(max = 2000))]
+    #[serde(default)]
+    message: String,
+    /// The IDs of the requested libraries, from the catalogue in `GET /api/requests/config`.
+    #[garde(length(min = 1, max = 100))]
+    libraries: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, garde::Validate)]
+pub struct AccessRequestInviteRequest {
+    /// The approved libraries, defaults to the requested ones.
+    #[garde(inner(length(min = 1)))]
+    libraries: Option<Vec<String>>,
+    #[garde(skip)]
+    roles: Option<Vec<String>>,
+    /// Expire the invite this many seconds after it's created, a week by default.
+    #[serde(rename = "expiresIn")]
+    #[garde(skip)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    #[garde(skip)]
+    format: TokenFormat,
+}
+
+/// Keep the known libraries once each, refusing the ones missing from the catalogue.
+async fn check_libraries(
+    requested: Vec<String>,
+) -> Result<Vec<String>, (StatusCode, Json<serde_json::Value>)> {
+    let komga = KomgaClient::instance();
+    let available: Vec<String> = match komga.get_libraries().await {
+        Ok(libraries) => libraries
+            .into_iter()
+            .filter(|library| !library.unavailable)
+            .map(|library| library.id)
+            .collect(),
+        Err(_) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Failed to get libraries from Komga"
+                })),
+            ))
+        }
+    };
+
+    let mut libraries: Vec<String> = vec![];
+    for library in requested {
+        if !available.contains(&library) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Invalid request:\n- libraries: Unknown library\n",
+                    "fields": {
+                        "libraries": ["Unknown library"]
+                    }
+                })),
+            ));
+        }
+        if !libraries.contains(&library) {
+            libraries.push(library);
+        }
+    }
+
+    Ok(libraries)
+}
+
+/// The public catalogue for the request form, only the library names.
+async fn get_request_config() -> impl IntoResponse {
+    if !accessrequest::is_enabled() {
+        return (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "enabled": false,
+                    "libraries": [],
+                }
+            })),
+        );
+    }
+
+    let komga = KomgaClient::instance();
+    match komga.get_libraries().await {
+        Ok(libraries) => {
+            let libraries: Vec<_> = libraries
+                .iter()
+                .filter(|library| !library.unavailable)
+                .map(|library| {
+                    serde_json::json!({
+                        "id": library.id,
+                        "name": library.name,
+                    })
+                })
+                .collect();
+
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "ok": true,
+                    "data": {
+                        "enabled": true,
+                        "libraries": libraries,
+                    }
+                })),
+            )
+        }
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Failed to get libraries from Komga"
+            })),
+        ),
+    }
+}
+
+async fn create_access_request(
+    State(state): State<AppState>,
+    Json(request): Json<AccessRequestCreateRequest>,
+) -> impl IntoResponse {
+    if !accessrequest::is_enabled() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Access requests are disabled"
+            })),
+        );
+    }
+
+    if let Err(e) = request.validate(&()) {
+        let mut format_err = String::new();
+        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+        for (field, err) in e.iter() {
+            format_err.push_str(&format!("- {}: {}", field, err));
+            format_err.push('\n');
+            fields
+                .entry(field.to_string())
+                .or_default()
+                .push(err.to_string());
+        }
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Invalid request:\n{}", format_err),
+                "fields": fields
+            })),
+        );
+    }
+
+    let email = email::normalize(&request.email);
+    if let Err(error) = email::check_domain(&email, None, None) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Invalid request:\n- email: {}\n", error),
+                "fields": {
+                    "email": [error.to_string()]
+                }
+            })),
+        );
+    }
+
+    // Only the libraries from the catalogue can be requested
+    let libraries = match check_libraries(request.libraries).await {
+        Ok(libraries) => libraries,
+        Err(response) => return response,
+    };
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let requests = match accessrequest::get_access_requests(&mut redis_conn).await {
+        Ok(requests) => requests,
+        Err(error) => {
+            error!("Failed to get access requests: {}", error);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Failed to submit the request"
+                })),
+            );
+        }
+    };
+    let pending = requests
+        .iter()
+        .filter(|existing| existing.status == AccessRequestStatus::Pending)
+        .count();
+    if pending >= accessrequest::max_pending() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Too many pending requests, please try again later"
+            })),
+        );
+    }
+
+    // Answer the same for a duplicate, so the form doesn't tell who already asked
+    let duplicate = requests.iter().any(|existing| {
+        existing.status == AccessRequestStatus::Pending && email::matches(&existing.email, &email)
+    });
+    if !duplicate {
+        let access_request =
+            AccessRequest::new(email, request.message.trim().to_string(), libraries);
+        if let Err(error) =
+            accessrequest::save_access_request(&mut redis_conn, &access_request).await
+        {
+            error!("Failed to save access request: {}", error);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Failed to submit the request"
+                })),
+            );
+        }
+        info!("[{}] New access request", access_request.id);
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "ok": true,
+            "data": {}
+        })),
+    )
+}
+
+async fn get_access_requests(
+    State(state): State<AppState>,
+    _: AuthToken<permission::RequestRead>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match accessrequest::get_access_requests(&mut redis_conn).await {
+        Ok(requests) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": requests,
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get access requests: {}", error)
+            })),
+        ),
+    }
+}
+
+/// Take the lock to decide the request and read it, it must still be pending.
+///
+/// The lock is held until [`accessrequest::unlock_deciding`], so two admins can't decide the
+/// same request at once.
+async fn lock_pending_request(
+    redis_conn: &mut MultiplexedConnection,
+    id: &str,
+) -> Result<AccessRequest, (StatusCode, Json<serde_json::Value>)> {
+    if !accessrequest::lock_deciding(redis_conn, id)
+        .await
+        .unwrap_or(false)
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Access request is already being decided"
+            })),
+        ));
+    }
+
+    let error = match accessrequest::get_access_request(redis_conn, id).await {
+        Ok(Some(access_request)) if access_request.status == AccessRequestStatus::Pending => {
+            return Ok(access_request)
+        }
+        Ok(Some(_)) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Access request was already decided"
+            })),
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Access request not found"
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get access request: {}", error)
+            })),
+        ),
+    };
+
+    accessrequest::unlock_deciding(redis_conn, id)
+        .await
+        .unwrap_or(());
+    Err(error)
+}
+
+/// Turn the request into an invite bound to the requester's email, for the approved libraries.
+async fn invite_access_request(
+    State(state): State<AppState>,
+    _: AuthToken<permission::RequestInvite>,
+    Path(id): Path<String>,
+    Json(request): Json<AccessRequestInviteRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = request.validate(&()) {
+        let mut format_err = String::new();
+        for (field, err) in e.iter() {
+            format_err.push_str(&format!("- {}: {}", field, err));
+            format_err.push('\n');
+        }
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Invalid request:\n{}", format_err)
+            })),
+        );
+    }
+
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut access_request = match lock_pending_request(&mut redis_conn, &id).await {
+        Ok(access_request) => access_request,
+        Err(response) => return response,
+    };
+
+    let response = create_request_invite(&mut redis_conn, &mut access_request, request).await;
+
+    accessrequest::unlock_deciding(&mut redis_conn, &id)
+        .await
+        .unwrap_or(());
+
+    response
+}
+
+/// Create the invite of the pending request, while holding the lock to decide it.
+async fn create_request_invite(
+    redis_conn: &mut MultiplexedConnection,
+    access_request: &mut AccessRequest,
+    request: AccessRequestInviteRequest,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let id = access_request.id.clone();
+
+    // The libraries could have been removed since the request was made
+    let libraries = match check_libraries(
+        request
+            .libraries
+            .unwrap_or_else(|| access_request.libraries.clone()),
+    )
+    .await
+    {
+        Ok(libraries) => libraries,
+        Err(response) => return response,
+    };
+
+    let option = InviteOption {
+        labels_allow: None,
+        labels_exclude: None,
+        shared_libraries: Some(KomgaUserCreateOptionSharedLibraries {
+            all: false,
+            library_ids: libraries,
+        }),
+        expire_at: Some(
+            chrono::Utc::now().timestamp() as u64
+                + request.expires_in.unwrap_or(DEFAULT_INVITE_EXPIRY),
+        ),
+        not_before: None,
+        expires_after_view: None,
+        roles: request.roles,
+        email_domains_allow: None,
+        email_domains_deny: None,
+        generate_password: None,
+        requires_approval: None,
+        questions: None,
+        bound_email: Some(access_request.email.clone()),
+        pow_difficulty: None,
+    };
+
+    let (token, invite_token) =
+        match store_new_invite(redis_conn, &option, request.format, None).await {
+            Ok(Some(created)) => created,
+            Ok(None) => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": "Failed to generate an unused invite code"
+                    })),
+                )
+            }
+            Err(error) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({
+                        "ok": false,
+                        "error": format!("Failed to create invite token: {}", error)
+                    })),
+                )
+            }
+        };
+
+    access_request.decide(
+        AccessRequestStatus::Invited,
+        Some(invite_token.id().to_string()),
+    );
+    if let Err(error) = accessrequest::save_access_request(redis_conn, access_request).await {
+        error!("[{}] Failed to save access request: {}", id, error);
+    }
+    info!("[{}] Invited access request ({})", id, invite_token.id());
+
+    // This is the only time the plaintext token is shown
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "ok": true,
+            "data": {
+                "request": access_request,
+                "invite": invite_token.with_token(&token),
+                // Only with `PUBLIC_URL`, the link is never built from the request headers
+                "url": qr::configured_public_url().map(|base_url| qr::invite_url(&base_url, &token)),
+            }
+        })),
+    )
+}
+
+async fn reject_access_request(
+    State(state): State<AppState>,
+    _: AuthToken<permission::RequestReview>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let mut access_request = match lock_pending_request(&mut redis_conn, &id).await {
+        Ok(access_request) => access_request,
+        Err(response) => return response,
+    };
+
+    access_request.decide(AccessRequestStatus::Rejected, None);
+    let response = match accessrequest::save_access_request(&mut redis_conn, &access_request).await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": access_request,
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to save access request: {}", error)
+            })),
+        ),
+    };
+
+    accessrequest::unlock_deciding(&mut redis_conn, &id)
+        .await
+        .unwrap_or(());
+
+    response
+}
+
+async fn delete_access_request(
+    State(state): State<AppState>,
+    _: AuthToken<permission::RequestReview>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = accessrequest::delete_access_request(&mut redis_conn, &id)
+        .await
+        .unwrap_or(false);
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
+}
+
+pub fn requests_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/",
+            axum::routing::post(create_access_request)
+                .layer(middleware::from_fn_with_state(
+                    (
+                        state.clone(),
+                        RateLimit::from_env("access_request", 3, 3600),
+                    ),
+                    rate_limit,
+                ))
+                .get(get_access_requests),
+        )
+        .route("/config", axum::routing::get(get_request_config))
+        .route("/:id", axum::routing::delete(delete_access_request))
+        .route("/:id/invite", axum::routing::post(invite_access_request))
+        .route("/:id/reject", axum::routing::post(reject_access_request))
+        .with_state(state)
+}
@@ -0,0 +1,185 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json, Router};
+
+use crate::{totp, AppState};
+
+use super::AuthToken;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TotpCodeForm {
+    code: String,
+}
+
+async fn totp_status(State(state): State<AppState>, _: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match totp::get_config(&mut redis_conn).await {
+        Ok(config) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "enabled": config.is_enabled(),
+                    "recoveryCodesLeft": config.recovery_codes_left(),
+                }
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to get TOTP config: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn totp_enroll(State(state): State<AppState>, _: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match totp::get_config(&mut redis_conn).await {
+        Ok(config) if config.is_enabled() => {
+            return (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "TOTP is already enabled, disable it first"
+                })),
+            );
+        }
+        Ok(_) => {}
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": format!("Failed to get TOTP config: {}", error)
+                })),
+            );
+        }
+    }
+
+    match totp::start_enrollment(&mut redis_conn).await {
+        Ok(secret) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "uri": totp::provisioning_uri(&secret),
+                    "secret": secret,
+                }
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to start TOTP enrollment: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn totp_confirm(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Json(payload): Json<TotpCodeForm>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match totp::confirm_enrollment(&mut redis_conn, &payload.code).await {
+        Ok(Some(recovery_codes)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": {
+                    "recoveryCodes": recovery_codes,
+                }
+            })),
+        ),
+        Ok(None) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "Invalid code or no pending enrollment"
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to confirm TOTP enrollment: {}", error)
+            })),
+        ),
+    }
+}
+
+async fn totp_disable(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Json(payload): Json<TotpCodeForm>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match totp::verify_code(&mut redis_conn, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": "Invalid code"
+                })),
+            );
+        }
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error": format!("Failed to verify code: {}", error)
+                })),
+            );
+        }
+    }
+
+    match totp::disable(&mut redis_conn).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+            })),
+        ),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": format!("Failed to disable TOTP: {}", error)
+            })),
+        ),
+    }
+}
+
+pub fn totp_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", axum::routing::get(totp_status))
+        .route("/enroll", axum::routing::post(totp_enroll))
+        .route("/confirm", axum::routing::post(totp_confirm))
+        .route("/disable", axum::routing::post(totp_disable))
+        .with_state(state)
+}
@@ -0,0 +1,184 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json, Router,
+};
+
+use crate::{
+    webauthn::{self, AssertionResponse, AttestationResponse, PublicKeyCredential, WebAuthnError},
+    AppState,
+};
+
+use super::{
+    auth::{issue_session, user_agent},
+    AuthToken,
+};
+
+#[derive(serde::Deserialize)]
+pub struct RegisterFinishForm {
+    name: String,
+    credential: PublicKeyCredential<AttestationResponse>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LoginFinishForm {
+    credential: PublicKeyCredential<AssertionResponse>,
+}
+
+fn error_response(error: WebAuthnError) -> (StatusCode, Json<serde_json::Value>) {
+    let status = match error {
+        WebAuthnError::NotConfigured => StatusCode::NOT_FOUND,
+        WebAuthnError::Redis(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        WebAuthnError::UnknownCredential | WebAuthnError::InvalidSignature => {
+            StatusCode::UNAUTHORIZED
+        }
+        _ => StatusCode::BAD_REQUEST,
+    };
+
+    (
+        status,
+        Json(serde_json::json!({
+            "ok": false,
+            "error": error.to_string()
+        })),
+    )
+}
+
+async fn register_start(State(state): State<AppState>, _: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match webauthn::start_registration(&mut redis_conn).await {
+        Ok(options) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": options,
+            })),
+        ),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn register_finish(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Json(payload): Json<RegisterFinishForm>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let name = match payload.name.trim() {
+        "" => "Passkey".to_string(),
+        name => name.to_string(),
+    };
+
+    match webauthn::finish_registration(&mut redis_conn, name, payload.credential).await {
+        Ok(credential) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": credential,
+            })),
+        ),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn login_start(State(state): State<AppState>) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match webauthn::start_authentication(&mut redis_conn).await {
+        Ok(options) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": options,
+            })),
+        ),
+        Err(error) => error_response(error),
+    }
+}
+
+async fn login_finish(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginFinishForm>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match webauthn::finish_authentication(&mut redis_conn, payload.credential).await {
+        Ok(_) => {
+            let (status, Json(response)) =
+                issue_session(&mut redis_conn, user_agent(&headers)).await;
+
+            (status, Json(serde_json::to_value(response).unwrap()))
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+async fn get_credentials(State(state): State<AppState>, _: AuthToken) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    match webauthn::get_credentials(&mut redis_conn).await {
+        Ok(credentials) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "ok": true,
+                "data": credentials,
+            })),
+        ),
+        Err(error) => error_response(WebAuthnError::Redis(error)),
+    }
+}
+
+async fn remove_credential(
+    State(state): State<AppState>,
+    _: AuthToken,
+    Path(credential_id): Path<String>,
+) -> impl IntoResponse {
+    let mut redis_conn = state
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let ok = webauthn::remove_credential(&mut redis_conn, &credential_id)
+        .await
+        .unwrap_or(false);
+
+    Json(serde_json::json!({
+        "ok": ok,
+    }))
+}
+
+pub fn webauthn_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/register/start", axum::routing::post(register_start))
+        .route("/register/finish", axum::routing::post(register_finish))
+        .route("/login/start", axum::routing::post(login_start))
+        .route("/login/finish", axum::routing::post(login_finish))
+        .route("/credentials", axum::routing::get(get_credentials))
+        .route("/credentials/:id", axum::routing::delete(remove_credential))
+        .with_state(state)
+}
@@ -0,0 +1,164 @@
+use std::sync::OnceLock;
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Get the hashes of the inline scripts in the built `index.html`, so the CSP can allow
+/// exactly those without `'unsafe-inline'`.
+fn inline_script_hashes(index_html: &str) -> Vec<String> {
+    let mut hashes = vec![];
+    let mut rest = index_html;
+
+    while let Some(start) = rest.find("<script") {
+        rest = &rest[start..];
+        let tag_end = match rest.find('>') {
+            Some(tag_end) => tag_end,
+            None => break,
+        };
+        let tag = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        let content_end = match rest.find("</script>") {
+            Some(content_end) => content_end,
+            None => break,
+        };
+        let content = &rest[..content_end];
+        rest = &rest[content_end..];
+
+        if !tag.contains("src=") && !content.trim().is_empty() {
+            hashes.push(format!(
+                "'sha256-{}'",
+                STANDARD.encode(Sha256::digest(content.as_bytes()))
+            ));
+        }
+    }
+
+    hashes
+}
+
+/// The Content-Security-Policy, from `CONTENT_SECURITY_POLICY` or a strict default
+/// matching the built frontend.
+fn content_security_policy(index_html: &str) -> &'static HeaderValue {
+    static CSP: OnceLock<HeaderValue> = OnceLock::new();
+
+    CSP.get_or_init(|| {
+        let custom = std::env::var("CONTENT_SECURITY_POLICY")
+            .ok()
+            .map(|csp| csp.trim().to_string())
+            .filter(|csp| !csp.is_empty())
+            .and_then(|csp| match HeaderValue::from_str(&csp) {
+                Ok(csp) => Some(csp),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid `CONTENT_SECURITY_POLICY`");
+                    None
+                }
+            });
+        if let Some(custom) = custom {
+            return custom;
+        }
+
+        let mut script_src = vec!["'self'".to_string()];
+        script_src.extend(inline_script_hashes(index_html));
+
+        let csp = [
+            "default-src 'self'".to_string(),
+            format!("script-src {}", script_src.join(" ")),
+            // Vue and some components inject inline styles
+            "style-src 'self' 'unsafe-inline'".to_string(),
+            "img-src 'self' data:".to_string(),
+            "font-src 'self' data:".to_string(),
+            "connect-src 'self'".to_string(),
+            "object-src 'none'".to_string(),
+            "base-uri 'self'".to_string(),
+            "form-action 'self'".to_string(),
+            "frame-ancestors 'none'".to_string(),
+        ]
+        .join("; ");
+
+        HeaderValue::from_str(&csp).unwrap()
+    })
+}
+
+/// The `Strict-Transport-Security` header, only sent if `HSTS_MAX_AGE` is set.
+fn strict_transport_security() -> Option<&'static HeaderValue> {
+    static HSTS: OnceLock<Option<HeaderValue>> = OnceLock::new();
+
+    HSTS.get_or_init(|| {
+        std::env::var("HSTS_MAX_AGE")
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .filter(|max_age| *max_age > 0)
+            .map(|max_age| {
+                HeaderValue::from_str(&format!("max-age={}; includeSubDomains", max_age)).unwrap()
+            })
+    })
+    .as_ref()
+}
+
+/// Middleware that add the security headers to every response.
+pub async fn security_headers(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        content_security_policy(crate::INDEX_HTML).clone(),
+    );
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    // The invite links carry the token in the URL, never leak it to other sites
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    if let Some(hsts) = strict_transport_security() {
+        headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+    }
+
+    response
+}
+
+/// The CORS layer from the `CORS_ORIGINS` environment variable.
+///
+/// By default no cross-origin request is allowed, `*` allow every origin.
+pub fn cors_layer() -> CorsLayer {
+    let origins = std::env::var("CORS_ORIGINS").unwrap_or_default();
+    let origins: Vec<&str> = origins
+        .split(',')
+        .map(|origin| origin.trim())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    let allow_origin = if origins.contains(&"*") {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|origin| {
+            match HeaderValue::from_str(origin.trim_end_matches('/')) {
+                Ok(origin) => Some(origin),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid origin in `CORS_ORIGINS`: {}", origin);
+                    None
+                }
+            }
+        }))
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-requested-with"),
+        ])
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+const KLIBRARIAN_SESSIONS: &str = "k-librarian:sessions";
+const KLIBRARIAN_SESSION_TOKEN: &str = "k-librarian:session_tokens";
+/// Default session lifetime (12 hours), renewed on every authenticated request.
+const DEFAULT_SESSION_TTL: u64 = 60 * 60 * 12;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Session {
+    pub id: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: u64,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: u64,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+}
+
+/// Get the session TTL in seconds from the `SESSION_TTL` environment variable.
+pub fn session_ttl() -> u64 {
+    std::env::var("SESSION_TTL")
+        .ok()
+        .and_then(|ttl| ttl.trim().parse::<u64>().ok())
+        .filter(|ttl| *ttl > 0)
+        .unwrap_or(DEFAULT_SESSION_TTL)
+}
+
+/// Hash the session token, we never store the raw token in Redis.
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn token_key(token: &str) -> String {
+    format!("{}:{}", KLIBRARIAN_SESSION_TOKEN, hash_token(token))
+}
+
+/// Create a new session, returning the raw bearer token and the session info.
+///
+/// The raw token is only known to the caller, Redis only holds the hash of it.
+pub async fn create_session(
+    redis_conn: &mut MultiplexedConnection,
+    user_agent: Option<String>,
+) -> Result<(String, Session), redis::RedisError> {
+    let ttl = session_ttl();
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let session = Session {
+        id: uuid::Uuid::new_v4().to_string(),
+        created_at: current_unix,
+        last_seen_at: current_unix,
+        expires_at: current_unix + ttl,
+        user_agent,
+    };
+
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_SESSIONS,
+            session.id.clone(),
+            serde_json::to_string(&session).unwrap(),
+        )
+        .await?;
+    let _: () = redis_conn
+        .set_ex(token_key(&token), session.id.clone(), ttl)
+        .await?;
+
+    Ok((token, session))
+}
+
+/// Verify the session token and slide the expiry forward.
+///
+/// Returns `None` if the token is unknown, expired or the session got revoked.
+pub async fn verify_session(
+    redis_conn: &mut MultiplexedConnection,
+    token: &str,
+) -> Result<Option<Session>, redis::RedisError> {
+    let key = token_key(token);
+    let session_id: Option<String> = redis_conn.get(&key).await?;
+
+    let session_id = match session_id {
+        Some(session_id) => session_id,
+        None => return Ok(None),
+    };
+
+    let data: Option<String> = redis_conn.hget(KLIBRARIAN_SESSIONS, &session_id).await?;
+    let mut session: Session = match data.and_then(|data| serde_json::from_str(&data).ok()) {
+        Some(session) => session,
+        None => {
+            // The session got revoked, remove the dangling token
+            let _: i32 = redis_conn.del(&key).await.unwrap_or(0);
+            return Ok(None);
+        }
+    };
+
+    let ttl = session_ttl();
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+    session.last_seen_at = current_unix;
+    session.expires_at = current_unix + ttl;
+
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_SESSIONS,
+            session.id.clone(),
+            serde_json::to_string(&session).unwrap(),
+        )
+        .await?;
+    let _: i32 = redis_conn.expire(&key, ttl as i64).await?;
+
+    Ok(Some(session))
+}
+
+/// Get all active sessions, stale sessions are cleaned up along the way.
+pub async fn get_sessions(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<Vec<Session>, redis::RedisError> {
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+    let all_sessions: HashMap<String, String> = redis_conn.hgetall(KLIBRARIAN_SESSIONS).await?;
+
+    let mut sessions = vec![];
+    for (id, value) in all_sessions {
+        match serde_json::from_str::<Session>(&value) {
+            Ok(session) if session.expires_at > current_unix => sessions.push(session),
+            _ => {
+                let _: i32 = redis_conn.hdel(KLIBRARIAN_SESSIONS, id).await.unwrap_or(0);
+            }
+        }
+    }
+
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+    Ok(sessions)
+}
+
+/// Revoke a session by the ID, the token will be rejected on the next use.
+pub async fn revoke_session(
+    redis_conn: &mut MultiplexedConnection,
+    session_id: &str,
+) -> Result<bool, redis::RedisError> {
+    let removed: i32 = redis_conn.hdel(KLIBRARIAN_SESSIONS, session_id).await?;
+
+    Ok(removed > 0)
+}
@@ -0,0 +1,423 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use redis::aio::MultiplexedConnection;
+use sha2::Sha256;
+
+use crate::{
+    email,
+    komga::KomgaUserCreateOptionSharedLibraries,
+    routes::invite::{parse_timestamp, InviteOption},
+};
+
+const KLIBRARIAN_SPENT_NONCES: &str = "k-librarian:spent_nonces";
+const KLIBRARIAN_FIRST_VIEWS: &str = "k-librarian:signed_first_views";
+/// The prefix of the stateless invite tokens, `s1.<payload>.<signature>`.
+pub const SIGNED_PREFIX: &str = "s1.";
+/// How long the nonce of a non-expiring invite is remembered.
+const SPENT_TTL_NO_EXPIRY: u64 = 60 * 60 * 24 * 365 * 10;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+pub enum SignedLinkError {
+    NotConfigured,
+    Invalid,
+    Spent,
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for SignedLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignedLinkError::NotConfigured => write!(f, "Signed invites are not configured"),
+            SignedLinkError::Invalid => write!(f, "Invalid invite signature"),
+            SignedLinkError::Spent => write!(f, "Invite token already used"),
+            SignedLinkError::Redis(error) => write!(f, "Redis error: {}", error),
+        }
+    }
+}
+
+impl From<redis::RedisError> for SignedLinkError {
+    fn from(error: redis::RedisError) -> Self {
+        SignedLinkError::Redis(error)
+    }
+}
+
+/// The content of a stateless invite, everything the server needs is in the link itself.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SignedInvite {
+    pub nonce: String,
+    pub option: InviteOption,
+}
+
+/// The signing key from `INVITE_SIGNING_KEY`, stateless invites are disabled without it.
+fn signing_key() -> Option<Vec<u8>> {
+    std::env::var("INVITE_SIGNING_KEY")
+        .ok()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+        .map(|key| key.into_bytes())
+}
+
+fn sign(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key size");
+    mac.update(SIGNED_PREFIX.as_bytes());
+    mac.update(payload.as_bytes());
+    mac
+}
+
+pub fn is_signed(token: &str) -> bool {
+    token.starts_with(SIGNED_PREFIX)
+}
+
+/// Create a signed invite token for the option.
+pub fn mint(option: InviteOption) -> Result<String, SignedLinkError> {
+    let key = signing_key().ok_or(SignedLinkError::NotConfigured)?;
+
+    Ok(mint_with(&key, option))
+}
+
+fn mint_with(key: &[u8], option: InviteOption) -> String {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let invite = SignedInvite {
+        nonce: URL_SAFE_NO_PAD.encode(nonce),
+        option,
+    };
+
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&invite).unwrap());
+    let signature = URL_SAFE_NO_PAD.encode(sign(key, &payload).finalize().into_bytes());
+
+    format!("{}{}.{}", SIGNED_PREFIX, payload, signature)
+}
+
+/// Verify the signature of the token, this does not check whether it was already used.
+pub fn verify(token: &str) -> Result<SignedInvite, SignedLinkError> {
+    let key = signing_key().ok_or(SignedLinkError::NotConfigured)?;
+
+    verify_with(&key, token)
+}
+
+fn verify_with(key: &[u8], token: &str) -> Result<SignedInvite, SignedLinkError> {
+    let (payload, signature) = token
+        .strip_prefix(SIGNED_PREFIX)
+        .and_then(|token| token.split_once('.'))
+        .ok_or(SignedLinkError::Invalid)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| SignedLinkError::Invalid)?;
+    sign(key, payload)
+        .verify_slice(&signature)
+        .map_err(|_| SignedLinkError::Invalid)?;
+
+    URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|payload| serde_json::from_slice(&payload).ok())
+        .ok_or(SignedLinkError::Invalid)
+}
+
+fn spent_key(nonce: &str) -> String {
+    format!("{}:{}", KLIBRARIAN_SPENT_NONCES, nonce)
+}
+
+pub async fn is_spent(
+    redis_conn: &mut MultiplexedConnection,
+    nonce: &str,
+) -> Result<bool, SignedLinkError> {
+    let exists: bool = redis::cmd("EXISTS")
+        .arg(spent_key(nonce))
+        .query_async(redis_conn)
+        .await?;
+
+    Ok(exists)
+}
+
+/// How long to remember the spent nonce, at least until the invite expires.
+fn spent_ttl(expire_at: Option<u64>, current_unix: u64) -> u64 {
+    match expire_at {
+        Some(expire_at) => expire_at.saturating_sub(current_unix).max(1),
+        None => SPENT_TTL_NO_EXPIRY,
+    }
+}
+
+/// Mark the nonce as spent, fails if somebody already used it.
+///
+/// The nonce is only remembered until the invite expires, afterwards the link is invalid anyway.
+pub async fn spend(
+    redis_conn: &mut MultiplexedConnection,
+    nonce: &str,
+    expire_at: Option<u64>,
+) -> Result<(), SignedLinkError> {
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+    let ttl = spent_ttl(expire_at, current_unix);
+
+    let first_use: Option<String> = redis::cmd("SET")
+        .arg(spent_key(nonce))
+        .arg(current_unix)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl)
+        .query_async(redis_conn)
+        .await?;
+
+    match first_use {
+        Some(_) => Ok(()),
+        None => Err(SignedLinkError::Spent),
+    }
+}
+
+/// Remember when the invite was first viewed, for `expiresAfterView`, and return that time.
+///
+/// Only a timestamp is kept for the nonce (until the invite expires), the invite itself is
+/// never stored just because the link was opened.
+pub async fn first_view(
+    redis_conn: &mut MultiplexedConnection,
+    nonce: &str,
+    expire_at: Option<u64>,
+) -> Result<u64, SignedLinkError> {
+    let key = format!("{}:{}", KLIBRARIAN_FIRST_VIEWS, nonce);
+    let current_unix = chrono::Utc::now().timestamp() as u64;
+
+    let _: Option<String> = redis::cmd("SET")
+        .arg(&key)
+        .arg(current_unix)
+        .arg("NX")
+        .arg("EX")
+        .arg(spent_ttl(expire_at, current_unix))
+        .query_async(redis_conn)
+        .await?;
+    let first_viewed_at: Option<u64> = redis::cmd("GET").arg(&key).query_async(redis_conn).await?;
+
+    Ok(first_viewed_at.unwrap_or(current_unix))
+}
+
+/// Give the nonce back, e.g. when creating the user failed.
+pub async fn unspend(
+    redis_conn: &mut MultiplexedConnection,
+    nonce: &str,
+) -> Result<(), SignedLinkError> {
+    let _: i32 = redis::cmd("DEL")
+        .arg(spent_key(nonce))
+        .query_async(redis_conn)
+        .await?;
+
+    Ok(())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+const CLI_USAGE: &str = "Usage: k-librarian mint-invite [options]
+
+Mint a stateless signed invite link, signed with `INVITE_SIGNING_KEY`.
+
+Options:
+  --expires-in <seconds>     Expire the invite after this many seconds
+  --expires-at <date>        Expire the invite at this date (ISO-8601 or unix seconds)
+  --expires-after-view <seconds> Expire the invite this many seconds after it's first opened
+  --not-before <date>        Only allow using the invite from this date (ISO-8601 or unix seconds)
+  --libraries <id,...>       Share only these libraries (default: all)
+  --labels-allow <label,...> Only allow these labels
+  --labels-exclude <label,...> Exclude these labels
+  --email <address>          Only allow registering with this email address
+  --generate-password        Generate the password, the invitee only give their email
+  --requires-approval        Hold the redemptions until an admin approve them
+  --roles <role,...>         The Komga roles (default: USER,FILE_DOWNLOAD,PAGE_STREAMING)
+  --url <base url>           Print the full invite link, e.g. https://librarian.example.com";
+
+/// The options of a new invite, sharing all the libraries with the default roles.
+fn default_option() -> InviteOption {
+    InviteOption {
+        labels_allow: None,
+        labels_exclude: None,
+        shared_libraries: Some(KomgaUserCreateOptionSharedLibraries {
+            all: true,
+            library_ids: vec![],
+        }),
+        expire_at: None,
+        not_before: None,
+        expires_after_view: None,
+        roles: None,
+        email_domains_allow: None,
+        email_domains_deny: None,
+        generate_password: None,
+        requires_approval: None,
+        questions: None,
+        bound_email: None,
+        pow_difficulty: None,
+    }
+}
+
+/// The `mint-invite` command, prints the signed token (or link) to stdout.
+pub fn cli(args: &[String]) -> Result<(), String> {
+    let mut option = default_option();
+    let mut base_url: Option<String> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", CLI_USAGE);
+            return Ok(());
+        }
+        if arg == "--generate-password" {
+            option.generate_password = Some(true);
+            continue;
+        }
+        if arg == "--requires-approval" {
+            option.requires_approval = Some(true);
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or(format!("Missing value for `{}`\n\n{}", arg, CLI_USAGE))?;
+        match arg.as_str() {
+            "--expires-in" => {
+                let seconds = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid `--expires-in`: {}", value))?;
+                option.expire_at = Some(chrono::Utc::now().timestamp() as u64 + seconds);
+            }
+            "--expires-at" => option.expire_at = Some(parse_timestamp(value)?),
+            "--expires-after-view" => {
+                let seconds = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid `--expires-after-view`: {}", value))?;
+                option.expires_after_view = Some(seconds);
+            }
+            "--not-before" => option.not_before = Some(parse_timestamp(value)?),
+            "--libraries" => {
+                option.shared_libraries = Some(KomgaUserCreateOptionSharedLibraries {
+                    all: false,
+                    library_ids: split_list(value),
+                });
+            }
+            "--labels-allow" => option.labels_allow = Some(split_list(value)),
+            "--labels-exclude" => option.labels_exclude = Some(split_list(value)),
+            "--roles" => option.roles = Some(split_list(value)),
+            "--email" => {
+                let bound_email = email::normalize(value);
+                if !email::is_valid(&bound_email) {
+                    return Err(format!("Invalid `--email`: {}", value));
+                }
+                option.bound_email = Some(bound_email);
+            }
+            "--url" => base_url = Some(value.trim_end_matches('/').to_string()),
+            _ => return Err(format!("Unknown option `{}`\n\n{}", arg, CLI_USAGE)),
+        }
+    }
+
+    let token = mint(option).map_err(|error| error.to_string())?;
+    match base_url {
+        Some(base_url) => println!("{}/invite?token={}", base_url, token),
+        None => println!("{}", token),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"test-signing-key";
+
+    fn option_expiring_at(expire_at: u64) -> InviteOption {
+        let mut option = default_option();
+        option.expire_at = Some(expire_at);
+        option
+    }
+
+    fn payload(token: &str) -> serde_json::Value {
+        let payload = token
+            .strip_prefix(SIGNED_PREFIX)
+            .and_then(|token| token.split_once('.'))
+            .unwrap()
+            .0;
+
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+    }
+
+    /// Replace the payload of the token, keeping the original signature.
+    fn with_payload(token: &str, payload: &serde_json::Value) -> String {
+        let signature = token.rsplit_once('.').unwrap().1;
+
+        format!(
+            "{}{}.{}",
+            SIGNED_PREFIX,
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(payload).unwrap()),
+            signature
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let token = mint_with(KEY, option_expiring_at(1_700_000_000));
+        let invite = verify_with(KEY, &token).unwrap();
+
+        assert!(is_signed(&token));
+        assert_eq!(invite.option.expire_at, Some(1_700_000_000));
+        assert_eq!(invite.nonce, payload(&token)["nonce"]);
+    }
+
+    #[test]
+    fn nonces_are_unique() {
+        let first = verify_with(KEY, &mint_with(KEY, default_option())).unwrap();
+        let second = verify_with(KEY, &mint_with(KEY, default_option())).unwrap();
+
+        assert_ne!(first.nonce, second.nonce);
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let token = mint_with(KEY, option_expiring_at(1_700_000_000));
+
+        // pushing the expiry back
+        let mut extended = payload(&token);
+        extended["option"]["expiresAt"] = serde_json::json!(4_000_000_000u64);
+        assert!(matches!(
+            verify_with(KEY, &with_payload(&token, &extended)),
+            Err(SignedLinkError::Invalid)
+        ));
+
+        // removing the expiry
+        let mut unlimited = payload(&token);
+        unlimited["option"]
+            .as_object_mut()
+            .unwrap()
+            .remove("expiresAt");
+        assert!(verify_with(KEY, &with_payload(&token, &unlimited)).is_err());
+
+        // replaying with a new nonce
+        let mut renonced = payload(&token);
+        renonced["nonce"] = serde_json::json!("AAAAAAAAAAAAAAAAAAAAAA");
+        assert!(verify_with(KEY, &with_payload(&token, &renonced)).is_err());
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let token = mint_with(KEY, default_option());
+
+        assert!(verify_with(b"another-key", &token).is_err());
+        assert!(verify_with(KEY, &format!("{}A", token)).is_err());
+        assert!(verify_with(KEY, token.rsplit_once('.').unwrap().0).is_err());
+        assert!(verify_with(KEY, &token.replacen(SIGNED_PREFIX, "s2.", 1)).is_err());
+    }
+
+    #[test]
+    fn spent_nonce_is_kept_until_expiry() {
+        let now = 1_700_000_000;
+
+        assert_eq!(spent_ttl(Some(now + 3600), now), 3600);
+        // already expired, still remembered for a moment
+        assert_eq!(spent_ttl(Some(now - 3600), now), 1);
+        assert_eq!(spent_ttl(None, now), SPENT_TTL_NO_EXPIRY);
+    }
+}
@@ -0,0 +1,369 @@
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const KLIBRARIAN_TOTP: &str = "k-librarian:totp";
+const KLIBRARIAN_LOGIN_CHALLENGE: &str = "k-librarian:login_challenges";
+const TOTP_ISSUER: &str = "K-Librarian";
+const TOTP_PERIOD: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// How many steps before and after the current one we accept, to allow some clock drift.
+const TOTP_SKEW: u64 = 1;
+const RECOVERY_CODES_COUNT: usize = 10;
+/// How long the second login step can take before the challenge expires.
+const LOGIN_CHALLENGE_TTL: u64 = 60 * 5;
+const LOGIN_CHALLENGE_MAX_ATTEMPTS: u32 = 5;
+/// How many times a code is checked again when the config changed in the meantime.
+const SAVE_RETRIES: usize = 5;
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub struct TotpConfig {
+    /// The active base32 secret, `None` if TOTP is not enabled.
+    secret: Option<String>,
+    /// The secret waiting to be confirmed with a valid code.
+    pending_secret: Option<String>,
+    /// SHA-256 hashes of the unused recovery codes.
+    recovery_codes: Vec<String>,
+    /// The last accepted time step, codes can't be reused.
+    last_step: u64,
+}
+
+impl TotpConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    pub fn recovery_codes_left(&self) -> usize {
+        self.recovery_codes.len()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LoginChallenge {
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+}
+
+fn hash_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / TOTP_PERIOD
+}
+
+/// Generate the HOTP value for the counter (RFC 4226).
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC can take key of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Find the time step the code is valid for (RFC 6238), within the allowed skew.
+fn find_step(secret: &str, code: &str) -> Option<u64> {
+    let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    let code = code.trim().replace(' ', "");
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let step = current_step();
+    (step.saturating_sub(TOTP_SKEW)..=step + TOTP_SKEW).find(|&step| hotp(&secret, step) == code)
+}
+
+fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+}
+
+fn generate_recovery_code() -> String {
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        .take(10)
+        .map(char::from)
+        .collect();
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// The `otpauth://` URI for the secret, to be shown as a QR code to the authenticator app.
+pub fn provisioning_uri(secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:admin?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = urlencoding::encode(TOTP_ISSUER),
+        secret = secret,
+        digits = TOTP_DIGITS,
+        period = TOTP_PERIOD,
+    )
+}
+
+pub async fn get_config(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<TotpConfig, redis::RedisError> {
+    let data: Option<String> = redis_conn.get(KLIBRARIAN_TOTP).await?;
+
+    Ok(parse_config(data))
+}
+
+fn parse_config(data: Option<String>) -> TotpConfig {
+    data.and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Save the config only if it's still `previous`, so a code can't be consumed twice.
+async fn replace_config(
+    redis_conn: &mut MultiplexedConnection,
+    previous: Option<&str>,
+    config: &TotpConfig,
+) -> Result<bool, redis::RedisError> {
+    let replaced: i32 = redis::Script::new(
+        r"if (redis.call('GET', KEYS[1]) or '') == ARGV[1] then
+            redis.call('SET', KEYS[1], ARGV[2])
+            return 1
+        end
+        return 0",
+    )
+    .key(KLIBRARIAN_TOTP)
+    .arg(previous.unwrap_or_default())
+    .arg(serde_json::to_string(config).unwrap())
+    .invoke_async(redis_conn)
+    .await?;
+
+    Ok(replaced == 1)
+}
+
+async fn save_config(
+    redis_conn: &mut MultiplexedConnection,
+    config: &TotpConfig,
+) -> Result<(), redis::RedisError> {
+    redis_conn
+        .set(KLIBRARIAN_TOTP, serde_json::to_string(config).unwrap())
+        .await
+}
+
+/// Start the enrollment, returning the new secret that needs to be confirmed.
+pub async fn start_enrollment(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<String, redis::RedisError> {
+    let mut config = get_config(redis_conn).await?;
+    let secret = generate_secret();
+
+    config.pending_secret = Some(secret.clone());
+    save_config(redis_conn, &config).await?;
+
+    Ok(secret)
+}
+
+/// Confirm the pending enrollment with a code, returning the recovery codes on success.
+pub async fn confirm_enrollment(
+    redis_conn: &mut MultiplexedConnection,
+    code: &str,
+) -> Result<Option<Vec<String>>, redis::RedisError> {
+    let mut config = get_config(redis_conn).await?;
+
+    let pending_secret = match config.pending_secret.clone() {
+        Some(pending_secret) => pending_secret,
+        None => return Ok(None),
+    };
+
+    match find_step(&pending_secret, code) {
+        Some(step) => {
+            let recovery_codes: Vec<String> = (0..RECOVERY_CODES_COUNT)
+                .map(|_| generate_recovery_code())
+                .collect();
+
+            config.secret = Some(pending_secret);
+            config.pending_secret = None;
+            config.recovery_codes = recovery_codes.iter().map(|code| hash_code(code)).collect();
+            config.last_step = step;
+            save_config(redis_conn, &config).await?;
+
+            Ok(Some(recovery_codes))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Verify a TOTP code or a recovery code against the active secret.
+///
+/// Recovery codes can only be used once, and so does a TOTP code, even with concurrent requests.
+pub async fn verify_code(
+    redis_conn: &mut MultiplexedConnection,
+    code: &str,
+) -> Result<bool, redis::RedisError> {
+    for _ in 0..SAVE_RETRIES {
+        let data: Option<String> = redis_conn.get(KLIBRARIAN_TOTP).await?;
+        let mut config = parse_config(data.clone());
+
+        if !consume_code(&mut config, code) {
+            return Ok(false);
+        }
+        if replace_config(redis_conn, data.as_deref(), &config).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Check the code against the config, marking it as used on success.
+fn consume_code(config: &mut TotpConfig, code: &str) -> bool {
+    let secret = match &config.secret {
+        Some(secret) => secret,
+        None => return false,
+    };
+
+    if let Some(step) = find_step(secret, code) {
+        if step <= config.last_step {
+            return false;
+        }
+
+        config.last_step = step;
+        return true;
+    }
+
+    let code_hash = hash_code(&code.trim().to_lowercase());
+    match config.recovery_codes.iter().position(|c| c == &code_hash) {
+        Some(index) => {
+            config.recovery_codes.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+pub async fn disable(redis_conn: &mut MultiplexedConnection) -> Result<(), redis::RedisError> {
+    redis_conn.del(KLIBRARIAN_TOTP).await
+}
+
+/// Create a challenge for the second login step, returning the challenge ID.
+pub async fn create_login_challenge(
+    redis_conn: &mut MultiplexedConnection,
+    user_agent: Option<String>,
+) -> Result<String, redis::RedisError> {
+    let challenge_id = uuid::Uuid::new_v4().to_string();
+    let challenge = LoginChallenge { user_agent };
+
+    let _: () = redis_conn
+        .set_ex(
+            format!("{}:{}", KLIBRARIAN_LOGIN_CHALLENGE, challenge_id),
+            serde_json::to_string(&challenge).unwrap(),
+            LOGIN_CHALLENGE_TTL,
+        )
+        .await?;
+
+    Ok(challenge_id)
+}
+
+/// Verify the code for the login challenge.
+///
+/// Every code counts as an attempt before it's checked, the challenge is consumed on success
+/// or after too many attempts.
+pub async fn verify_login_challenge(
+    redis_conn: &mut MultiplexedConnection,
+    challenge_id: &str,
+    code: &str,
+) -> Result<Option<LoginChallenge>, redis::RedisError> {
+    let key = format!("{}:{}", KLIBRARIAN_LOGIN_CHALLENGE, challenge_id);
+    let attempts_key = format!("{}:attempts", key);
+
+    let data: Option<String> = redis::Script::new(
+        r"local attempts = redis.call('INCR', KEYS[2])
+        if attempts == 1 then
+            redis.call('EXPIRE', KEYS[2], ARGV[1])
+        end
+        if attempts > tonumber(ARGV[2]) then
+            redis.call('DEL', KEYS[1], KEYS[2])
+            return false
+        end
+        return redis.call('GET', KEYS[1])",
+    )
+    .key(&key)
+    .key(&attempts_key)
+    .arg(LOGIN_CHALLENGE_TTL)
+    .arg(LOGIN_CHALLENGE_MAX_ATTEMPTS)
+    .invoke_async(redis_conn)
+    .await?;
+
+    let challenge: LoginChallenge = match data.and_then(|data| serde_json::from_str(&data).ok()) {
+        Some(challenge) => challenge,
+        None => return Ok(None),
+    };
+
+    if verify_code(redis_conn, code).await? {
+        // Only one request gets to consume the challenge
+        let deleted: i32 = redis_conn.del(&key).await?;
+        let _: i32 = redis_conn.del(&attempts_key).await?;
+        if deleted == 1 {
+            return Ok(Some(challenge));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_secret() -> (TotpConfig, Vec<u8>) {
+        let secret = generate_secret();
+        let raw = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret).unwrap();
+        let config = TotpConfig {
+            secret: Some(secret),
+            recovery_codes: vec![hash_code("abcde-12345")],
+            ..Default::default()
+        };
+
+        (config, raw)
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226() {
+        let secret = b"12345678901234567890";
+        let expected = [755224, 287082, 359152, 969429, 338314];
+        for (counter, value) in expected.iter().enumerate() {
+            assert_eq!(hotp(secret, counter as u64), *value);
+        }
+    }
+
+    #[test]
+    fn totp_code_is_consumed_once() {
+        let (mut config, raw) = config_with_secret();
+        let code = format!("{:06}", hotp(&raw, current_step()));
+
+        assert!(consume_code(&mut config, &code));
+        assert!(!consume_code(&mut config, &code));
+    }
+
+    #[test]
+    fn recovery_code_is_consumed_once() {
+        let (mut config, _) = config_with_secret();
+
+        assert!(consume_code(&mut config, " ABCDE-12345 "));
+        assert_eq!(config.recovery_codes_left(), 0);
+        assert!(!consume_code(&mut config, "abcde-12345"));
+    }
+
+    #[test]
+    fn rejects_codes_without_secret() {
+        let mut config = TotpConfig::default();
+        assert!(!consume_code(&mut config, "123456"));
+    }
+}
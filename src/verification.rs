@@ -0,0 +1,246 @@
+use rand::Rng;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+use crate::{bruteforce::constant_time_eq, email, mailer, qr};
+
+const KLIBRARIAN_EMAIL_VERIFICATION: &str = "k-librarian:email_verification";
+/// Wrong codes before the code is thrown away and a new one must be requested.
+const MAX_ATTEMPTS: u32 = 5;
+/// Don't send another code before this many seconds, asking again keeps the current one.
+const RESEND_COOLDOWN: u64 = 60;
+/// Codes sent for one invite within `SENDS_WINDOW`, whatever the address.
+const MAX_SENDS: u64 = 10;
+const SENDS_WINDOW: u64 = 60 * 60;
+
+#[derive(Debug)]
+pub enum VerificationError {
+    Invalid,
+    TooManyAttempts,
+    TooManySends,
+    Mail(mailer::MailerError),
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::Invalid => write!(f, "The verification code is wrong or expired"),
+            VerificationError::TooManyAttempts => write!(
+                f,
+                "Too many wrong verification codes, please request a new one"
+            ),
+            VerificationError::TooManySends => write!(
+                f,
+                "Too many verification emails for this invite, please try again later"
+            ),
+            VerificationError::Mail(error) => write!(f, "{}", error),
+            VerificationError::Redis(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<redis::RedisError> for VerificationError {
+    fn from(error: redis::RedisError) -> Self {
+        VerificationError::Redis(error)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PendingVerification {
+    code_hash: String,
+}
+
+/// Check if `EMAIL_VERIFICATION=true`, whether or not it can be enabled.
+pub fn is_requested() -> bool {
+    std::env::var("EMAIL_VERIFICATION")
+        .map(|value| value.trim().to_lowercase())
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Check if the invitees must verify their email, `EMAIL_VERIFICATION=true` with SMTP and
+/// `PUBLIC_URL` configured.
+///
+/// The links in the emails are never built from the request headers, which can be forged.
+pub fn is_enabled() -> bool {
+    is_requested() && mailer::is_enabled() && qr::configured_public_url().is_some()
+}
+
+/// How long the code is valid, from `EMAIL_VERIFICATION_TTL` (15 minutes by default).
+fn code_ttl() -> u64 {
+    std::env::var("EMAIL_VERIFICATION_TTL")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|ttl| *ttl > 0)
+        .unwrap_or(15 * 60)
+}
+
+/// The code is for one email on one invite, the address is hashed to keep it out of the key.
+fn verification_key(invite_id: &str, address: &str) -> String {
+    format!(
+        "{}:{}:{:x}",
+        KLIBRARIAN_EMAIL_VERIFICATION,
+        invite_id,
+        Sha256::digest(email::normalize(address).as_bytes())
+    )
+}
+
+fn hash_code(key: &str, code: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(format!("{}:{}", key, code).as_bytes())
+    )
+}
+
+/// Send a one-time code to the address, the registration is held until it's given back.
+///
+/// `link` builds the link to continue the registration with the code. At most `MAX_SENDS`
+/// codes are sent for an invite per hour, so it can't be used to flood mailboxes.
+pub async fn send_code(
+    redis_conn: &mut MultiplexedConnection,
+    invite_id: &str,
+    address: &str,
+    link: impl FnOnce(&str) -> String,
+) -> Result<(), VerificationError> {
+    let key = verification_key(invite_id, address);
+    let cooldown_key = format!("{}:cooldown", key);
+
+    let cooling_down: Option<String> = redis::cmd("SET")
+        .arg(&cooldown_key)
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(RESEND_COOLDOWN)
+        .query_async(redis_conn)
+        .await?;
+    if cooling_down.is_none() {
+        return Ok(());
+    }
+
+    let sends_key = format!("{}:{}:sends", KLIBRARIAN_EMAIL_VERIFICATION, invite_id);
+    let sends: u64 = redis_conn.incr(&sends_key, 1).await?;
+    if sends == 1 {
+        let _: i32 = redis_conn.expire(&sends_key, SENDS_WINDOW as i64).await?;
+    }
+    if sends > MAX_SENDS {
+        return Err(VerificationError::TooManySends);
+    }
+
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    let ttl = code_ttl();
+    let pending = PendingVerification {
+        code_hash: hash_code(&key, &code),
+    };
+    let _: () = redis_conn
+        .set_ex(&key, serde_json::to_string(&pending).unwrap(), ttl)
+        .await?;
+    let _: i32 = redis_conn.del(format!("{}:attempts", key)).await?;
+
+    let body = format!(
+        "Your K-Librarian verification code is: {}\n\n\
+        Enter it on the invite page, or continue your registration with this link:\n{}\n\n\
+        The code expires in {} minutes. If you didn't ask for it, you can ignore this email.\n",
+        code,
+        link(&code),
+        ttl.div_ceil(60)
+    );
+    if let Err(error) = mailer::send(address, "Verify your email address", body).await {
+        let _: i32 = redis_conn.del(&[&key, &cooldown_key]).await?;
+        return Err(VerificationError::Mail(error));
+    }
+
+    Ok(())
+}
+
+/// Check the code for the address, the code is consumed on success or after too many wrong ones.
+///
+/// Every guess counts as an attempt before it's compared, so concurrent guesses can't go past
+/// `MAX_ATTEMPTS`.
+pub async fn check_code(
+    redis_conn: &mut MultiplexedConnection,
+    invite_id: &str,
+    address: &str,
+    code: &str,
+) -> Result<(), VerificationError> {
+    let key = verification_key(invite_id, address);
+    let attempts_key = format!("{}:attempts", key);
+
+    let (attempts, data): (u32, String) = redis::Script::new(
+        r"local attempts = redis.call('INCR', KEYS[2])
+        if attempts == 1 then
+            redis.call('EXPIRE', KEYS[2], ARGV[1])
+        end
+        if attempts > tonumber(ARGV[2]) then
+            redis.call('DEL', KEYS[1])
+            return {attempts, ''}
+        end
+        return {attempts, redis.call('GET', KEYS[1]) or ''}",
+    )
+    .key(&key)
+    .key(&attempts_key)
+    .arg(code_ttl())
+    .arg(MAX_ATTEMPTS)
+    .invoke_async(redis_conn)
+    .await?;
+    if attempts > MAX_ATTEMPTS {
+        return Err(VerificationError::TooManyAttempts);
+    }
+
+    let pending: PendingVerification = match serde_json::from_str(&data) {
+        Ok(pending) => pending,
+        Err(_) => return Err(VerificationError::Invalid),
+    };
+
+    if constant_time_eq(&hash_code(&key, code.trim()), &pending.code_hash) {
+        // Only one request gets to use the code
+        let deleted: i32 = redis_conn.del(&key).await?;
+        let _: i32 = redis_conn.del(&attempts_key).await?;
+        return match deleted {
+            1 => Ok(()),
+            _ => Err(VerificationError::Invalid),
+        };
+    }
+
+    if attempts >= MAX_ATTEMPTS {
+        let _: i32 = redis_conn.del(&key).await?;
+        return Err(VerificationError::TooManyAttempts);
+    }
+
+    Err(VerificationError::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_is_per_normalized_address() {
+        let key = verification_key("invite", "Jane.Doe@Example.org");
+
+        assert_eq!(key, verification_key("invite", " jane.doe@example.org\n"));
+        assert_ne!(key, verification_key("invite", "john.doe@example.org"));
+        assert_ne!(key, verification_key("other", "jane.doe@example.org"));
+    }
+
+    #[test]
+    fn key_hides_the_address() {
+        let key = verification_key("invite", "jane.doe@example.org");
+
+        assert!(key.starts_with("k-librarian:email_verification:invite:"));
+        assert!(!key.contains("jane"));
+        assert!(!key.contains("example"));
+    }
+
+    #[test]
+    fn code_hash_depends_on_key_and_code() {
+        let key = verification_key("invite", "jane.doe@example.org");
+        let other_key = verification_key("invite", "john.doe@example.org");
+        let hash = hash_code(&key, "123456");
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_code(&key, "123456"));
+        assert_ne!(hash, hash_code(&key, "123457"));
+        assert_ne!(hash, hash_code(&other_key, "123456"));
+    }
+}
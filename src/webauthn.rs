@@ -0,0 +1,801 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value as CborValue;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use sha2::{Digest, Sha256};
+
+const KLIBRARIAN_WEBAUTHN_CREDENTIALS: &str = "k-librarian:webauthn_credentials";
+const KLIBRARIAN_WEBAUTHN_CHALLENGE: &str = "k-librarian:webauthn_challenges";
+const CHALLENGE_TTL: u64 = 60 * 5;
+/// The user handle of the admin, there's only one admin account.
+const ADMIN_USER_ID: &[u8] = b"k-librarian-admin";
+/// COSE algorithm identifier for ES256 (ECDSA w/ SHA-256 on P-256).
+const COSE_ALG_ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug)]
+pub enum WebAuthnError {
+    NotConfigured,
+    InvalidChallenge,
+    InvalidClientData(&'static str),
+    InvalidAuthenticatorData(&'static str),
+    UnsupportedAlgorithm,
+    UnknownCredential,
+    InvalidSignature,
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for WebAuthnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebAuthnError::NotConfigured => write!(f, "WebAuthn is not configured"),
+            WebAuthnError::InvalidChallenge => write!(f, "Invalid or expired challenge"),
+            WebAuthnError::InvalidClientData(reason) => {
+                write!(f, "Invalid client data: {}", reason)
+            }
+            WebAuthnError::InvalidAuthenticatorData(reason) => {
+                write!(f, "Invalid authenticator data: {}", reason)
+            }
+            WebAuthnError::UnsupportedAlgorithm => {
+                write!(
+                    f,
+                    "Unsupported credential algorithm, only ES256 is supported"
+                )
+            }
+            WebAuthnError::UnknownCredential => write!(f, "Unknown credential"),
+            WebAuthnError::InvalidSignature => write!(f, "Invalid signature"),
+            WebAuthnError::Redis(error) => write!(f, "Redis error: {}", error),
+        }
+    }
+}
+
+impl From<redis::RedisError> for WebAuthnError {
+    fn from(error: redis::RedisError) -> Self {
+        WebAuthnError::Redis(error)
+    }
+}
+
+/// The relying party configuration, from `WEBAUTHN_RP_ID` and `WEBAUTHN_ORIGIN`.
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn from_env() -> Result<Self, WebAuthnError> {
+        let id = std::env::var("WEBAUTHN_RP_ID").unwrap_or_default();
+        let origin = std::env::var("WEBAUTHN_ORIGIN").unwrap_or_default();
+
+        if id.trim().is_empty() || origin.trim().is_empty() {
+            return Err(WebAuthnError::NotConfigured);
+        }
+
+        Ok(RelyingParty {
+            id: id.trim().to_string(),
+            origin: origin.trim().trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Credential {
+    /// The base64url encoded credential ID.
+    pub id: String,
+    pub name: String,
+    /// The base64url encoded SEC1 uncompressed P-256 public key.
+    #[serde(rename = "publicKey")]
+    public_key: String,
+    #[serde(rename = "signCount")]
+    sign_count: u32,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<u64>,
+}
+
+/// The `response` of a `PublicKeyCredential` from `navigator.credentials.create()`.
+#[derive(serde::Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+/// The `response` of a `PublicKeyCredential` from `navigator.credentials.get()`.
+#[derive(serde::Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublicKeyCredential<T> {
+    #[serde(rename = "rawId")]
+    raw_id: String,
+    response: T,
+}
+
+#[derive(serde::Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    attested_data: &'a [u8],
+}
+
+/// The new credential from a verified attestation.
+struct AttestedCredential {
+    id: Vec<u8>,
+    /// The SEC1 uncompressed P-256 public key.
+    public_key: Vec<u8>,
+    sign_count: u32,
+}
+
+fn decode(data: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(data.trim_end_matches('=')).ok()
+}
+
+fn challenge_key(challenge: &str) -> String {
+    format!("{}:{}", KLIBRARIAN_WEBAUTHN_CHALLENGE, challenge)
+}
+
+/// Create a new random challenge for the ceremony.
+async fn create_challenge(
+    redis_conn: &mut MultiplexedConnection,
+    ceremony: &str,
+) -> Result<String, WebAuthnError> {
+    let mut challenge = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    let challenge = URL_SAFE_NO_PAD.encode(challenge);
+
+    let _: () = redis_conn
+        .set_ex(challenge_key(&challenge), ceremony, CHALLENGE_TTL)
+        .await?;
+
+    Ok(challenge)
+}
+
+/// Consume the challenge, a challenge can only be used once.
+///
+/// `GETDEL` makes it atomic, so concurrent ceremonies can't both use the same challenge.
+async fn consume_challenge(
+    redis_conn: &mut MultiplexedConnection,
+    challenge: &str,
+    ceremony: &str,
+) -> Result<(), WebAuthnError> {
+    let stored: Option<String> = redis_conn.get_del(challenge_key(challenge)).await?;
+
+    match stored {
+        Some(stored) if stored == ceremony => Ok(()),
+        _ => Err(WebAuthnError::InvalidChallenge),
+    }
+}
+
+/// Check the client data for the ceremony, returning the challenge to consume.
+fn parse_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    ceremony: &str,
+) -> Result<String, WebAuthnError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| WebAuthnError::InvalidClientData("malformed JSON"))?;
+
+    if client_data.kind != ceremony {
+        return Err(WebAuthnError::InvalidClientData("wrong ceremony type"));
+    }
+    if client_data.origin != rp.origin {
+        return Err(WebAuthnError::InvalidClientData("origin mismatch"));
+    }
+
+    Ok(client_data.challenge)
+}
+
+async fn verify_client_data(
+    redis_conn: &mut MultiplexedConnection,
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    ceremony: &str,
+) -> Result<(), WebAuthnError> {
+    let challenge = parse_client_data(rp, client_data_json, ceremony)?;
+
+    consume_challenge(redis_conn, &challenge, ceremony).await
+}
+
+fn parse_authenticator_data<'a>(
+    rp: &RelyingParty,
+    auth_data: &'a [u8],
+) -> Result<AuthenticatorData<'a>, WebAuthnError> {
+    if auth_data.len() < 37 {
+        return Err(WebAuthnError::InvalidAuthenticatorData("too short"));
+    }
+
+    let rp_id_hash = Sha256::digest(rp.id.as_bytes());
+    if auth_data[..32] != rp_id_hash[..] {
+        return Err(WebAuthnError::InvalidAuthenticatorData("RP ID mismatch"));
+    }
+
+    let flags = auth_data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebAuthnError::InvalidAuthenticatorData("user not present"));
+    }
+    // The passkey replaces both the password and TOTP, so the PIN or biometrics must be checked
+    if flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebAuthnError::InvalidAuthenticatorData("user not verified"));
+    }
+
+    Ok(AuthenticatorData {
+        flags,
+        sign_count: u32::from_be_bytes([
+            auth_data[33],
+            auth_data[34],
+            auth_data[35],
+            auth_data[36],
+        ]),
+        attested_data: &auth_data[37..],
+    })
+}
+
+fn cbor_map_get<'a>(map: &'a [(CborValue, CborValue)], key: &CborValue) -> Option<&'a CborValue> {
+    map.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Extract the P-256 public key from the COSE key, as a SEC1 uncompressed point.
+fn parse_cose_key(cose_key: &[u8]) -> Result<Vec<u8>, WebAuthnError> {
+    let key: CborValue = ciborium::de::from_reader(cose_key)
+        .map_err(|_| WebAuthnError::InvalidAuthenticatorData("malformed public key"))?;
+    let key = key.as_map().ok_or(WebAuthnError::InvalidAuthenticatorData(
+        "malformed public key",
+    ))?;
+
+    let alg = cbor_map_get(key, &CborValue::from(3))
+        .and_then(|alg| alg.as_integer())
+        .map(i128::from);
+    if alg != Some(COSE_ALG_ES256 as i128) {
+        return Err(WebAuthnError::UnsupportedAlgorithm);
+    }
+
+    let x = cbor_map_get(key, &CborValue::from(-2)).and_then(|x| x.as_bytes());
+    let y = cbor_map_get(key, &CborValue::from(-3)).and_then(|y| y.as_bytes());
+
+    match (x, y) {
+        (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+
+            VerifyingKey::from_sec1_bytes(&point)
+                .map_err(|_| WebAuthnError::InvalidAuthenticatorData("invalid public key"))?;
+            Ok(point)
+        }
+        _ => Err(WebAuthnError::InvalidAuthenticatorData(
+            "invalid public key",
+        )),
+    }
+}
+
+pub async fn get_credentials(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<Vec<Credential>, redis::RedisError> {
+    let all_credentials: HashMap<String, String> =
+        redis_conn.hgetall(KLIBRARIAN_WEBAUTHN_CREDENTIALS).await?;
+
+    let mut credentials: Vec<Credential> = all_credentials
+        .values()
+        .filter_map(|value| serde_json::from_str(value).ok())
+        .collect();
+
+    credentials.sort_by_key(|credential| credential.created_at);
+    Ok(credentials)
+}
+
+pub async fn remove_credential(
+    redis_conn: &mut MultiplexedConnection,
+    credential_id: &str,
+) -> Result<bool, redis::RedisError> {
+    let removed: i32 = redis_conn
+        .hdel(KLIBRARIAN_WEBAUTHN_CREDENTIALS, credential_id)
+        .await?;
+
+    Ok(removed > 0)
+}
+
+/// Start the registration ceremony, returning the `PublicKeyCredentialCreationOptions`.
+pub async fn start_registration(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<serde_json::Value, WebAuthnError> {
+    let rp = RelyingParty::from_env()?;
+    let challenge = create_challenge(redis_conn, "webauthn.create").await?;
+    let credentials = get_credentials(redis_conn).await?;
+
+    let exclude_credentials: Vec<serde_json::Value> = credentials
+        .iter()
+        .map(|credential| {
+            serde_json::json!({
+                "type": "public-key",
+                "id": credential.id,
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "challenge": challenge,
+        "rp": {
+            "id": rp.id,
+            "name": "K-Librarian",
+        },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(ADMIN_USER_ID),
+            "name": "admin",
+            "displayName": "K-Librarian Admin",
+        },
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": COSE_ALG_ES256 },
+        ],
+        "excludeCredentials": exclude_credentials,
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "required",
+        },
+        "attestation": "none",
+        "timeout": CHALLENGE_TTL * 1000,
+    }))
+}
+
+/// Verify the attestation object of a new credential, `raw_id` is the ID given by the browser.
+fn verify_attestation(
+    rp: &RelyingParty,
+    attestation_object: &[u8],
+    raw_id: &[u8],
+) -> Result<AttestedCredential, WebAuthnError> {
+    let attestation: CborValue = ciborium::de::from_reader(attestation_object)
+        .map_err(|_| WebAuthnError::InvalidAuthenticatorData("malformed attestation"))?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|map| cbor_map_get(map, &CborValue::from("authData")))
+        .and_then(|auth_data| auth_data.as_bytes())
+        .ok_or(WebAuthnError::InvalidAuthenticatorData("missing authData"))?;
+
+    let auth_data = parse_authenticator_data(rp, auth_data)?;
+    if auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(WebAuthnError::InvalidAuthenticatorData(
+            "missing attested credential data",
+        ));
+    }
+
+    // aaguid (16) | credential ID length (2) | credential ID | COSE public key
+    let attested = auth_data.attested_data;
+    if attested.len() < 18 {
+        return Err(WebAuthnError::InvalidAuthenticatorData("too short"));
+    }
+    let id_len = u16::from_be_bytes([attested[16], attested[17]]) as usize;
+    if attested.len() < 18 + id_len {
+        return Err(WebAuthnError::InvalidAuthenticatorData("too short"));
+    }
+    let credential_id = &attested[18..18 + id_len];
+    let public_key = parse_cose_key(&attested[18 + id_len..])?;
+
+    if raw_id != credential_id {
+        return Err(WebAuthnError::InvalidAuthenticatorData(
+            "credential ID mismatch",
+        ));
+    }
+
+    Ok(AttestedCredential {
+        id: credential_id.to_vec(),
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Finish the registration ceremony, storing the new credential.
+pub async fn finish_registration(
+    redis_conn: &mut MultiplexedConnection,
+    name: String,
+    credential: PublicKeyCredential<AttestationResponse>,
+) -> Result<Credential, WebAuthnError> {
+    let rp = RelyingParty::from_env()?;
+
+    let client_data_json = decode(&credential.response.client_data_json)
+        .ok_or(WebAuthnError::InvalidClientData("malformed base64"))?;
+    verify_client_data(redis_conn, &rp, &client_data_json, "webauthn.create").await?;
+
+    let attestation_object = decode(&credential.response.attestation_object)
+        .ok_or(WebAuthnError::InvalidAuthenticatorData("malformed base64"))?;
+    let raw_id = decode(&credential.raw_id).unwrap_or_default();
+    let attested = verify_attestation(&rp, &attestation_object, &raw_id)?;
+
+    let stored = Credential {
+        id: URL_SAFE_NO_PAD.encode(attested.id),
+        name,
+        public_key: URL_SAFE_NO_PAD.encode(attested.public_key),
+        sign_count: attested.sign_count,
+        created_at: chrono::Utc::now().timestamp() as u64,
+        last_used_at: None,
+    };
+
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_WEBAUTHN_CREDENTIALS,
+            stored.id.clone(),
+            serde_json::to_string(&stored).unwrap(),
+        )
+        .await?;
+
+    Ok(stored)
+}
+
+/// Start the authentication ceremony, returning the `PublicKeyCredentialRequestOptions`.
+pub async fn start_authentication(
+    redis_conn: &mut MultiplexedConnection,
+) -> Result<serde_json::Value, WebAuthnError> {
+    let rp = RelyingParty::from_env()?;
+    let challenge = create_challenge(redis_conn, "webauthn.get").await?;
+    let credentials = get_credentials(redis_conn).await?;
+
+    let allow_credentials: Vec<serde_json::Value> = credentials
+        .iter()
+        .map(|credential| {
+            serde_json::json!({
+                "type": "public-key",
+                "id": credential.id,
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "challenge": challenge,
+        "rpId": rp.id,
+        "allowCredentials": allow_credentials,
+        "userVerification": "required",
+        "timeout": CHALLENGE_TTL * 1000,
+    }))
+}
+
+/// Verify the assertion signature of a stored credential, returning the new signature counter.
+fn verify_assertion(
+    rp: &RelyingParty,
+    public_key: &[u8],
+    sign_count: u32,
+    client_data_json: &[u8],
+    raw_auth_data: &[u8],
+    signature: &[u8],
+) -> Result<u32, WebAuthnError> {
+    let auth_data = parse_authenticator_data(rp, raw_auth_data)?;
+
+    let verifying_key =
+        VerifyingKey::from_sec1_bytes(public_key).map_err(|_| WebAuthnError::UnknownCredential)?;
+    let signature = Signature::from_der(signature).map_err(|_| WebAuthnError::InvalidSignature)?;
+
+    let mut signed_data = raw_auth_data.to_vec();
+    signed_data.extend_from_slice(&Sha256::digest(client_data_json));
+    verifying_key
+        .verify(&signed_data, &signature)
+        .map_err(|_| WebAuthnError::InvalidSignature)?;
+
+    // A counter that doesn't move forward means the authenticator might be cloned
+    if (auth_data.sign_count != 0 || sign_count != 0) && auth_data.sign_count <= sign_count {
+        return Err(WebAuthnError::InvalidAuthenticatorData(
+            "signature counter did not increase",
+        ));
+    }
+
+    Ok(auth_data.sign_count)
+}
+
+/// Finish the authentication ceremony, verifying the assertion signature.
+pub async fn finish_authentication(
+    redis_conn: &mut MultiplexedConnection,
+    credential: PublicKeyCredential<AssertionResponse>,
+) -> Result<Credential, WebAuthnError> {
+    let rp = RelyingParty::from_env()?;
+
+    let credential_id = decode(&credential.raw_id)
+        .map(|id| URL_SAFE_NO_PAD.encode(id))
+        .ok_or(WebAuthnError::UnknownCredential)?;
+    let data: Option<String> = redis_conn
+        .hget(KLIBRARIAN_WEBAUTHN_CREDENTIALS, &credential_id)
+        .await?;
+    let mut stored: Credential = data
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .ok_or(WebAuthnError::UnknownCredential)?;
+
+    let client_data_json = decode(&credential.response.client_data_json)
+        .ok_or(WebAuthnError::InvalidClientData("malformed base64"))?;
+    verify_client_data(redis_conn, &rp, &client_data_json, "webauthn.get").await?;
+
+    let raw_auth_data = decode(&credential.response.authenticator_data)
+        .ok_or(WebAuthnError::InvalidAuthenticatorData("malformed base64"))?;
+    let public_key = decode(&stored.public_key).ok_or(WebAuthnError::UnknownCredential)?;
+    let signature =
+        decode(&credential.response.signature).ok_or(WebAuthnError::InvalidSignature)?;
+
+    stored.sign_count = verify_assertion(
+        &rp,
+        &public_key,
+        stored.sign_count,
+        &client_data_json,
+        &raw_auth_data,
+        &signature,
+    )?;
+    stored.last_used_at = Some(chrono::Utc::now().timestamp() as u64);
+    let _: i32 = redis_conn
+        .hset(
+            KLIBRARIAN_WEBAUTHN_CREDENTIALS,
+            stored.id.clone(),
+            serde_json::to_string(&stored).unwrap(),
+        )
+        .await?;
+
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    use super::*;
+
+    const ORIGIN: &str = "https://librarian.example.com";
+    const CREDENTIAL_ID: &[u8] = b"test-credential";
+
+    fn rp() -> RelyingParty {
+        RelyingParty {
+            id: "librarian.example.com".to_string(),
+            origin: ORIGIN.to_string(),
+        }
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7u8; 32]).unwrap()
+    }
+
+    fn client_data(kind: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": kind,
+            "challenge": "challenge",
+            "origin": origin,
+        }))
+        .unwrap()
+    }
+
+    fn make_auth_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    fn cose_key(alg: i64) -> Vec<u8> {
+        let point = signing_key().verifying_key().to_encoded_point(false);
+        let key = CborValue::Map(vec![
+            (CborValue::from(1), CborValue::from(2)),
+            (CborValue::from(3), CborValue::from(alg)),
+            (CborValue::from(-1), CborValue::from(1)),
+            (
+                CborValue::from(-2),
+                CborValue::Bytes(point.x().unwrap().to_vec()),
+            ),
+            (
+                CborValue::from(-3),
+                CborValue::Bytes(point.y().unwrap().to_vec()),
+            ),
+        ]);
+
+        let mut data = vec![];
+        ciborium::ser::into_writer(&key, &mut data).unwrap();
+        data
+    }
+
+    fn attestation_object(flags: u8, alg: i64) -> Vec<u8> {
+        let mut auth_data = make_auth_data("librarian.example.com", flags, 0);
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(CREDENTIAL_ID.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(CREDENTIAL_ID);
+        auth_data.extend_from_slice(&cose_key(alg));
+
+        let attestation = CborValue::Map(vec![
+            (CborValue::from("fmt"), CborValue::from("none")),
+            (CborValue::from("attStmt"), CborValue::Map(vec![])),
+            (CborValue::from("authData"), CborValue::Bytes(auth_data)),
+        ]);
+
+        let mut data = vec![];
+        ciborium::ser::into_writer(&attestation, &mut data).unwrap();
+        data
+    }
+
+    fn sign(auth_data: &[u8], client_data_json: &[u8]) -> Vec<u8> {
+        let mut signed_data = auth_data.to_vec();
+        signed_data.extend_from_slice(&Sha256::digest(client_data_json));
+        let signature: Signature = signing_key().sign(&signed_data);
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    fn public_key() -> Vec<u8> {
+        signing_key()
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    const FLAGS_VERIFIED: u8 = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+
+    #[test]
+    fn client_data_is_checked() {
+        let rp = rp();
+
+        assert_eq!(
+            parse_client_data(&rp, &client_data("webauthn.get", ORIGIN), "webauthn.get").unwrap(),
+            "challenge"
+        );
+        assert!(
+            parse_client_data(&rp, &client_data("webauthn.create", ORIGIN), "webauthn.get")
+                .is_err()
+        );
+        assert!(parse_client_data(
+            &rp,
+            &client_data("webauthn.get", "https://evil.example.com"),
+            "webauthn.get"
+        )
+        .is_err());
+        assert!(parse_client_data(&rp, b"not json", "webauthn.get").is_err());
+    }
+
+    #[test]
+    fn attestation_is_accepted() {
+        let attestation = attestation_object(FLAGS_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA, -7);
+        let attested = verify_attestation(&rp(), &attestation, CREDENTIAL_ID).unwrap();
+
+        assert_eq!(attested.id, CREDENTIAL_ID);
+        assert_eq!(attested.public_key, public_key());
+        assert_eq!(attested.sign_count, 0);
+    }
+
+    #[test]
+    fn attestation_is_rejected() {
+        let rp = rp();
+        let flags = FLAGS_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA;
+
+        // the browser reported another credential ID
+        let attestation = attestation_object(flags, -7);
+        assert!(verify_attestation(&rp, &attestation, b"other").is_err());
+        // not ES256
+        let attestation = attestation_object(flags, -257);
+        assert!(matches!(
+            verify_attestation(&rp, &attestation, CREDENTIAL_ID),
+            Err(WebAuthnError::UnsupportedAlgorithm)
+        ));
+        // no user verification
+        let attestation = attestation_object(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA, -7);
+        assert!(verify_attestation(&rp, &attestation, CREDENTIAL_ID).is_err());
+        // no attested credential data
+        let attestation = attestation_object(FLAGS_VERIFIED, -7);
+        assert!(verify_attestation(&rp, &attestation, CREDENTIAL_ID).is_err());
+        // registered for another RP
+        let other_rp = RelyingParty {
+            id: "evil.example.com".to_string(),
+            origin: ORIGIN.to_string(),
+        };
+        let attestation = attestation_object(flags, -7);
+        assert!(verify_attestation(&other_rp, &attestation, CREDENTIAL_ID).is_err());
+    }
+
+    #[test]
+    fn assertion_is_accepted() {
+        let client_data_json = client_data("webauthn.get", ORIGIN);
+        let auth_data = make_auth_data("librarian.example.com", FLAGS_VERIFIED, 5);
+        let signature = sign(&auth_data, &client_data_json);
+
+        let sign_count = verify_assertion(
+            &rp(),
+            &public_key(),
+            4,
+            &client_data_json,
+            &auth_data,
+            &signature,
+        )
+        .unwrap();
+        assert_eq!(sign_count, 5);
+
+        // authenticators without a counter always send 0
+        let auth_data = make_auth_data("librarian.example.com", FLAGS_VERIFIED, 0);
+        let signature = sign(&auth_data, &client_data_json);
+        assert!(verify_assertion(
+            &rp(),
+            &public_key(),
+            0,
+            &client_data_json,
+            &auth_data,
+            &signature
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn assertion_without_user_verification_is_rejected() {
+        let client_data_json = client_data("webauthn.get", ORIGIN);
+        let auth_data = make_auth_data("librarian.example.com", FLAG_USER_PRESENT, 5);
+        let signature = sign(&auth_data, &client_data_json);
+
+        assert!(verify_assertion(
+            &rp(),
+            &public_key(),
+            4,
+            &client_data_json,
+            &auth_data,
+            &signature
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn tampered_assertion_is_rejected() {
+        let rp = rp();
+        let client_data_json = client_data("webauthn.get", ORIGIN);
+        let auth_data = make_auth_data("librarian.example.com", FLAGS_VERIFIED, 5);
+        let signature = sign(&auth_data, &client_data_json);
+
+        // signed over other client data
+        let other_client_data = client_data("webauthn.get", "https://other.example.com");
+        assert!(matches!(
+            verify_assertion(
+                &rp,
+                &public_key(),
+                4,
+                &other_client_data,
+                &auth_data,
+                &signature
+            ),
+            Err(WebAuthnError::InvalidSignature)
+        ));
+        // signed by another key
+        let other_key = SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let other_public_key = other_key.verifying_key().to_encoded_point(false);
+        assert!(verify_assertion(
+            &rp,
+            other_public_key.as_bytes(),
+            4,
+            &client_data_json,
+            &auth_data,
+            &signature
+        )
+        .is_err());
+        // replayed counter
+        assert!(verify_assertion(
+            &rp,
+            &public_key(),
+            5,
+            &client_data_json,
+            &auth_data,
+            &signature
+        )
+        .is_err());
+        // truncated
+        assert!(verify_assertion(
+            &rp,
+            &public_key(),
+            4,
+            &client_data_json,
+            &auth_data[..36],
+            &signature
+        )
+        .is_err());
+    }
+}